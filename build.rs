@@ -0,0 +1,85 @@
+use std::{env, fs, path::Path};
+
+fn main() {
+    embed_down_migrations();
+    embed_frontend();
+}
+
+// Generates the table of down.sql files src/cli.rs reverts with, as embed_migrations! only
+// keeps up.sql. Versions are named like diesel does, the digits before the first _.
+fn embed_down_migrations() {
+    println!("cargo:rerun-if-changed=migrations");
+
+    let migrations = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("migrations");
+    let mut dirs: Vec<_> = fs::read_dir(&migrations)
+        .unwrap_or_else(|_| panic!("{} is missing", migrations.display()))
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.join("down.sql").is_file())
+        .collect();
+    dirs.sort();
+
+    let table: String = dirs
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            format!(
+                "    ({:?}, include_str!({:?})),\n",
+                name.split('_').next().unwrap_or_default().replace('-', ""),
+                path.join("down.sql").display().to_string()
+            )
+        })
+        .collect();
+
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("down_migrations.rs"),
+        format!(
+            "static DOWN_MIGRATIONS: &[(&str, &str)] = &[\n{}];\n",
+            table
+        ),
+    )
+    .unwrap();
+}
+
+// With the embed-frontend feature, generates the table of frontend/dist files src/assets.rs
+// serves from the binary
+fn embed_frontend() {
+    println!("cargo:rerun-if-changed=frontend/dist");
+
+    if env::var_os("CARGO_FEATURE_EMBED_FRONTEND").is_none() {
+        return;
+    }
+
+    let dist = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("frontend/dist");
+    let mut files: Vec<_> = fs::read_dir(&dist)
+        .unwrap_or_else(|_| panic!("{} is missing, run frontend/build.sh first", dist.display()))
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+
+    if !files.iter().any(|path| path.ends_with("index.html")) {
+        panic!(
+            "{} has no index.html, run frontend/build.sh first",
+            dist.display()
+        );
+    }
+
+    let table: String = files
+        .iter()
+        .map(|path| {
+            format!(
+                "    ({:?}, include_bytes!({:?})),\n",
+                path.file_name().unwrap().to_string_lossy(),
+                path.display().to_string()
+            )
+        })
+        .collect();
+
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("frontend.rs"),
+        format!("static FILES: &[(&str, &[u8])] = &[\n{}];\n", table),
+    )
+    .unwrap();
+}
@@ -1,9 +1,35 @@
 // Additional JS script
+var changes = null;
+
+// Live updates from other devices
+app.ports.listenChanges.subscribe((url) => {
+    if (changes) {
+        changes.close();
+    }
+
+    changes = new EventSource(url);
+    ["subscription.created", "subscription.updated", "subscription.deleted", "rates.refreshed", "resync"].forEach((event) => {
+        changes.addEventListener(event, () => app.ports.changed.send(event));
+    });
+});
+
 app.ports.signOut.subscribe(() => {
-    var auth2 = gapi.auth2.getAuthInstance();
-    auth2.signOut().then(() => {
+    const signedOut = () => {
+        if (changes) {
+            changes.close();
+            changes = null;
+        }
+
         app.ports.signedOut.send(null);
-    });
+    };
+
+    // Local accounts don't go through Google, which may not even load offline
+    if (typeof gapi === "undefined" || !gapi.auth2) {
+        signedOut();
+        return;
+    }
+
+    gapi.auth2.getAuthInstance().signOut().then(signedOut);
 });
 
 function onSignIn(googleUser) {
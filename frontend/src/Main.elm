@@ -20,6 +20,8 @@ import Time
 port loggedIn : (UserInfo -> msg) -> Sub msg
 port signedOut : (() -> msg) -> Sub msg
 port signOut : () -> Cmd msg
+port listenChanges : String -> Cmd msg
+port changed : (String -> msg) -> Sub msg
 
 type RequestStatus
   = NotAsked
@@ -32,8 +34,8 @@ type alias Email =
   , email: Maybe String
   , createdAt: Maybe String
   , updatedAt: Maybe String
-  , currencyId: Maybe Int
   , currencieId: Maybe Int
+  , version: Int
   }
 
 initialEmail : Email
@@ -43,7 +45,7 @@ initialEmail =
   , createdAt = Nothing
   , updatedAt = Nothing
   , currencieId = Nothing
-  , currencyId = Nothing
+  , version = 0
   }
 
 emailDecoder : Decoder Email
@@ -53,8 +55,8 @@ emailDecoder =
     |> Pipeline.required "email" (Decode.maybe Decode.string)
     |> Pipeline.required "created_at" (Decode.maybe Decode.string)
     |> Pipeline.required "updated_at" (Decode.maybe Decode.string)
-    |> Pipeline.required "currency_id" (Decode.maybe Decode.int)
     |> Pipeline.required "currencie_id" (Decode.maybe Decode.int)
+    |> Pipeline.optional "version" Decode.int 0
 
 emailEncoder : Email -> Encode.Value
 emailEncoder email =
@@ -63,8 +65,15 @@ emailEncoder email =
     , ( "email", (Encode.string) (Maybe.withDefault "" email.email) )
     , ( "created_at", (Encode.string) (Maybe.withDefault "" email.createdAt) )
     , ( "updated_at", (Encode.string) (Maybe.withDefault "" email.updatedAt) )
-    , ( "currency_id", (Encode.int) (Maybe.withDefault 0 email.currencyId) )
-    , ( "currencie_id", (Encode.int) (Maybe.withDefault 0 email.currencieId) )
+    , ( "currencie_id"
+      , case email.currencieId of
+          Just currencieId ->
+            Encode.int currencieId
+
+          _ ->
+            Encode.null
+      )
+    , ( "version", Encode.int email.version )
     ]
 
 type alias EmailSaveBody =
@@ -100,6 +109,7 @@ type alias Subscription =
   , createdAt: Maybe String
   , updatedAt: Maybe String
   , currencieId: Maybe Int
+  , version: Int
   }
 
 initialSubscription : Subscription
@@ -113,6 +123,7 @@ initialSubscription =
   , createdAt = Nothing
   , updatedAt = Nothing
   , currencieId = Nothing
+  , version = 0
   }
 
 subscriptionDecoder : Decoder Subscription
@@ -127,6 +138,7 @@ subscriptionDecoder =
     |> Pipeline.required "created_at" (Decode.maybe Decode.string)
     |> Pipeline.required "updated_at" (Decode.maybe Decode.string)
     |> Pipeline.required "currencie_id" (Decode.maybe Decode.int)
+    |> Pipeline.optional "version" Decode.int 0
   
 subscriptionEncoder : Subscription -> Encode.Value
 subscriptionEncoder subscription =
@@ -149,7 +161,14 @@ subscriptionEncoder subscription =
         )
     , ( "name", Encode.string  (Maybe.withDefault "" subscription.name) )
     , ( "cost", Encode.float  (Maybe.withDefault 0 subscription.cost) )
-    , ( "interval_id", Encode.int  (Maybe.withDefault 0 subscription.intervalId) )
+    , ( "interval_id"
+      , case subscription.intervalId of
+          Just intervalId ->
+            Encode.int intervalId
+
+          _ ->
+            Encode.null
+      )
     , ( "interval_amount", Encode.int  (Maybe.withDefault 0 subscription.intervalAmount) )
     , ( "created_at"
       , case subscription.createdAt of
@@ -175,6 +194,7 @@ subscriptionEncoder subscription =
           _ ->
             Encode.null
       )
+    , ( "version", Encode.int subscription.version )
     ]
 
 type alias Interval =
@@ -241,6 +261,22 @@ type alias GoogleUser =
     }
 
 
+-- Our own tokens, given for a Google id token or a local account login
+type alias Session =
+    { email : String
+    , accessToken : String
+    , refreshToken : String
+    , expiresIn : Int
+    }
+
+sessionDecoder : Decoder Session
+sessionDecoder =
+  Decode.succeed Session
+    |> Pipeline.required "email" Decode.string
+    |> Pipeline.required "access_token" Decode.string
+    |> Pipeline.required "refresh_token" Decode.string
+    |> Pipeline.required "expires_in" Decode.int
+
 googleUserDecoder : Decoder GoogleUser
 googleUserDecoder  =
   Decode.succeed GoogleUser
@@ -269,6 +305,7 @@ type Modification
   = NotModified
   | Unsaved
   | Saved
+  | Conflicted
 
 type alias Model = 
   { currentTime : Int
@@ -281,6 +318,12 @@ type alias Model =
   , requestStatus: RequestStatus
   , deleteIds : List Int
   , modified : Modification
+  , localAccounts : Bool
+  , loginUsername : String
+  , loginPassword : String
+  , loginError : Maybe String
+  , refreshToken : Maybe String
+  , sessionExpiresIn : Int
   }
 
 
@@ -299,10 +342,16 @@ init flags =
       , requestStatus = NotAsked
       , deleteIds = []
       , modified = NotModified
+      , localAccounts = False
+      , loginUsername = ""
+      , loginPassword = ""
+      , loginError = Nothing
+      , refreshToken = Nothing
+      , sessionExpiresIn = 0
       }
   in
   ( initialModel
-  , Cmd.none
+  , fetchLoginProviders initialModel
   )
 
 
@@ -310,9 +359,20 @@ init flags =
 
 type Msg 
   = NoOp 
+  | GoogleSignedIn UserInfo
+  | GotSession UserInfo (Result Http.Error Session)
+  | RefreshSession Time.Posix
+  | RefreshedSession (Result Http.Error Session)
+  | LoggedOut (Result Http.Error ())
   | LoggedIn UserInfo
   | SignOut
   | SignedOut ()
+  | Changed String
+  | GotLoginProviders (Result Http.Error Bool)
+  | InputLoginUsername String
+  | InputLoginPassword String
+  | SubmitLocalLogin
+  | LocalLoggedIn (Result Http.Error Session)
   | GotCurrencies (Result Http.Error (List Currencie))
   | GotIntervals (Result Http.Error (List Interval))
   | PostedSubscription (Result Http.Error ())
@@ -338,14 +398,91 @@ update msg model =
     LoggedIn userInfo ->
       let
         newModel = { model | user = Just userInfo }
+        ( fetchedModel, fetchCmd ) = fetchEmail newModel
       in
-      fetchEmail newModel
+      ( fetchedModel
+      , Cmd.batch
+          [ fetchCmd
+          , listenChanges (model.url ++ "/events?access_token=" ++ userInfo.idToken)
+          ]
+      )
+
+    GoogleSignedIn userInfo ->
+      ( model, startSession model userInfo )
+
+    GotSession userInfo res ->
+      case res of
+        Ok session ->
+          withSession userInfo session model
+
+        Err _ ->
+          ( { model | loginError = Just "Sign in failed" }, Cmd.none )
+
+    RefreshSession _ ->
+      case model.refreshToken of
+        Just refreshToken ->
+          ( model, refreshSession model refreshToken )
+
+        Nothing ->
+          ( model, Cmd.none )
+
+    RefreshedSession res ->
+      case ( res, model.user ) of
+        ( Ok session, Just user ) ->
+          ( { model
+              | user = Just { user | idToken = session.accessToken }
+              , refreshToken = Just session.refreshToken
+              , sessionExpiresIn = session.expiresIn
+            }
+          , listenChanges (model.url ++ "/events?access_token=" ++ session.accessToken)
+          )
+
+        -- Revoked or expired
+        _ ->
+          update SignOut model
+
+    LoggedOut _ ->
+      ( model, Cmd.none )
 
     SignOut -> 
-      ( model, signOut () )
+      ( { model | refreshToken = Nothing }, Cmd.batch [ logout model, signOut () ] )
 
     SignedOut _ ->
-      ( { model | user = Nothing }, Cmd.none )
+      ( { model | user = Nothing, refreshToken = Nothing }, Cmd.none )
+
+    GotLoginProviders res ->
+      ( { model | localAccounts = Result.withDefault False res }, Cmd.none )
+
+    InputLoginUsername username ->
+      ( { model | loginUsername = username }, Cmd.none )
+
+    InputLoginPassword password ->
+      ( { model | loginPassword = password }, Cmd.none )
+
+    SubmitLocalLogin ->
+      ( { model | loginError = Nothing }, localLogin model )
+
+    LocalLoggedIn res ->
+      case res of
+        Ok session ->
+          withSession
+            { fullName = session.email, imageUrl = "", email = session.email, idToken = "" }
+            session
+            model
+
+        Err _ ->
+          ( { model | loginError = Just "Invalid username or password" }, Cmd.none )
+
+    Changed event ->
+      if event == "rates.refreshed" then
+        ( model, fetchCurrencies model )
+
+      -- Don't overwrite edits that haven't been saved yet
+      else if model.modified == Unsaved then
+        ( model, Cmd.none )
+
+      else
+        fetchEmailSubscriptions model
 
     GotCurrencies res ->
       case res of
@@ -427,6 +564,10 @@ update msg model =
                 if model.modified == Unsaved then Saved else model.modified 
             }
 
+        -- Saved from another tab or device since this one loaded
+        Err (Http.BadStatus 412) ->
+          fetchEmail { model | modified = Conflicted, deleteIds = [] }
+
         _ ->
           ( model, Cmd.none  )
 
@@ -589,7 +730,7 @@ fetchEmailSubscriptions newModel =
                 _ ->
                   ""
               )
-          ++ "/subscriptions"
+          ++ "/subscriptions?active=true"
       , body = Http.emptyBody
       , expect = Http.expectJson GotSubscriptions (Decode.list subscriptionDecoder)
       , timeout = Nothing
@@ -661,14 +802,35 @@ view model =
             _ ->
               span [] []
         ]
+
+    , case ( model.user, model.localAccounts ) of
+        ( Nothing, True ) ->
+          Html.form [ class "d-flex flex-column my-2", onSubmit SubmitLocalLogin ]
+            [ input [ class "form-control my-1", placeholder "Username", value model.loginUsername, onInput InputLoginUsername ] []
+            , input [ class "form-control my-1", type_ "password", placeholder "Password", value model.loginPassword, onInput InputLoginPassword ] []
+            , button [ class "btn btn-primary", type_ "submit" ] [ text "Sign in" ]
+            , case model.loginError of
+                Just loginError ->
+                  div [ class "text-danger" ] [ text loginError ]
+
+                Nothing ->
+                  span [] []
+            ]
+
+        _ ->
+          span [] []
     
     -- , div [] [ text (String.fromInt model.currentTime) ]
     , case model.user of
         Just user ->
           div [ class "d-flex flex-column align-items-center justify-content-center" ]
             [ h5 [ class "text-center" ] [text <| "Hello, " ++ user.fullName ++ "! (" ++ user.email ++ ")"]
-            , div []
-                [ img [ class "rounded-pill", src user.imageUrl ] [] ]
+            , if user.imageUrl == "" then
+                span [] []
+
+              else
+                div []
+                  [ img [ class "rounded-pill", src user.imageUrl ] [] ]
             , div [ class "d-flex align-items-center" ]
                 [ div [ class "fw-bold" ] [ text "Currency:" ]
                 , select
@@ -740,6 +902,7 @@ view model =
                     NotModified -> div [] []
                     Unsaved -> div [ class "text-danger" ] [ text "Progress unsaved" ]
                     Saved -> div [ class "text-success" ] [ text "Saved!" ]
+                    Conflicted -> div [ class "text-warning" ] [ text "Changed on another device, reloaded" ]
                 ]
             , let
                 subscriptionsLength = (List.length model.subscriptions)
@@ -967,11 +1130,30 @@ subscriptionCard model i subscription =
             [ button [ class "btn btn-danger btn-sm", onClick (DeleteSubscription i subscription) ] [ text "Delete" ] ]
     ]
 
+-- Signs in with our session, the id token was only needed to start it
+withSession : UserInfo -> Session -> Model -> ( Model, Cmd Msg )
+withSession userInfo session model =
+  update
+    (LoggedIn { userInfo | idToken = session.accessToken })
+    { model
+      | refreshToken = Just session.refreshToken
+      , sessionExpiresIn = session.expiresIn
+      , loginPassword = ""
+    }
+
 subscriptions : Model -> Sub Msg
-subscriptions _ =
+subscriptions model =
   Sub.batch [
-    loggedIn LoggedIn,
-    signedOut SignedOut
+    loggedIn GoogleSignedIn,
+    signedOut SignedOut,
+    changed Changed,
+    -- Refresh a while before the access token expires
+    case model.refreshToken of
+      Just _ ->
+        Time.every (toFloat (max 60 model.sessionExpiresIn * 800)) RefreshSession
+
+      Nothing ->
+        Sub.none
   ]
 
 fetchIntervals : Model -> Cmd Msg
@@ -996,6 +1178,82 @@ fetchIntervals model =
     , tracker = Nothing
     }
     
+fetchLoginProviders : Model -> Cmd Msg
+fetchLoginProviders model =
+  Http.request
+    { method = "GET"
+    , headers = []
+    , url = model.url ++ "/login-providers"
+    , body = Http.emptyBody
+    , expect = Http.expectJson GotLoginProviders (Decode.field "local_accounts" Decode.bool)
+    , timeout = Nothing
+    , tracker = Nothing
+    }
+
+localLogin : Model -> Cmd Msg
+localLogin model =
+  Http.request
+    { method = "POST"
+    , headers = []
+    , url = model.url ++ "/login"
+    , body =
+        Http.jsonBody
+          (Encode.object
+            [ ( "username", Encode.string model.loginUsername )
+            , ( "password", Encode.string model.loginPassword )
+            ]
+          )
+    , expect = Http.expectJson LocalLoggedIn sessionDecoder
+    , timeout = Nothing
+    , tracker = Nothing
+    }
+
+startSession : Model -> UserInfo -> Cmd Msg
+startSession model userInfo =
+  Http.request
+    { method = "POST"
+    , headers = []
+    , url = model.url ++ "/google-login-verify"
+    , body = Http.jsonBody (Encode.object [ ( "id_token", Encode.string userInfo.idToken ) ])
+    , expect = Http.expectJson (GotSession userInfo) sessionDecoder
+    , timeout = Nothing
+    , tracker = Nothing
+    }
+
+refreshSession : Model -> String -> Cmd Msg
+refreshSession model refreshToken =
+  Http.request
+    { method = "POST"
+    , headers = []
+    , url = model.url ++ "/sessions/refresh"
+    , body = Http.jsonBody (Encode.object [ ( "refresh_token", Encode.string refreshToken ) ])
+    , expect = Http.expectJson RefreshedSession sessionDecoder
+    , timeout = Nothing
+    , tracker = Nothing
+    }
+
+logout : Model -> Cmd Msg
+logout model =
+  Http.request
+    { method = "POST"
+    , headers = 
+        [ Http.header 
+            "authorization" 
+            (case model.user of
+              Just user ->
+                user.idToken 
+              
+              _ ->
+                ""
+            )
+        ]
+    , url = model.url ++ "/logout"
+    , body = Http.emptyBody
+    , expect = Http.expectWhatever LoggedOut
+    , timeout = Nothing
+    , tracker = Nothing
+    }
+
 fetchCurrencies : Model -> Cmd Msg
 fetchCurrencies model  =
   Http.request
@@ -1071,6 +1329,9 @@ saveEmail model =
                     _ ->
                       ""
                   )
+              , Http.header
+                  "if-match"
+                  ("\"" ++ String.fromInt (Maybe.withDefault initialEmail model.email).version ++ "\"")
               ]
           , url 
               = model.url 
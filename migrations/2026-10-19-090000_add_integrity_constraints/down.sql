@@ -0,0 +1,84 @@
+-- This file should undo anything in `up.sql`
+-- The loose tables from before, duplicates that were merged stay merged
+create table currencies_old (
+    id integer primary key autoincrement,
+    created_at datetime default (datetime('now')),
+    updated_at datetime default (datetime('now')),
+    name text,
+    rate real,
+    last_update_day datetime
+);
+
+insert into currencies_old (id, created_at, updated_at, name, rate, last_update_day)
+select id, created_at, updated_at, name, rate, last_update_day from currencies;
+
+create table intervals_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    name text,
+    modifier real
+);
+
+insert into intervals_old (id, created_at, updated_at, name, modifier)
+select id, created_at, updated_at, name, modifier from intervals;
+
+create table emails_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email text,
+    currency_id integer,
+    currencie_id integer
+);
+
+insert into emails_old (id, created_at, updated_at, email, currencie_id)
+select id, created_at, updated_at, email, currencie_id from emails;
+
+create table subscriptions_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer,
+    name text,
+    cost real,
+    interval_id integer,
+    interval_amount integer,
+    currencie_id integer
+);
+
+insert into subscriptions_old (
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount, currencie_id
+)
+select id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount, currencie_id
+from subscriptions;
+
+drop table subscriptions;
+drop table emails;
+drop table intervals;
+drop table currencies;
+
+alter table currencies_old rename to currencies;
+alter table intervals_old rename to intervals;
+alter table emails_old rename to emails;
+alter table subscriptions_old rename to subscriptions;
+
+create trigger currencies_ts after insert on currencies
+begin
+    update currencies set updated_at=(datetime('now')) where id=new.id;
+end;
+
+create trigger intervals_ts after insert on intervals
+begin
+    update intervals set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger emails_ts after insert on emails
+begin
+    update emails set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscriptions_ts after insert on subscriptions
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,110 @@
+-- Your SQL goes here
+create table currencies_new (
+    id integer primary key autoincrement,
+    created_at datetime default (datetime('now')),
+    updated_at datetime default (datetime('now')),
+    name text not null unique,
+    rate real not null default 0,
+    last_update_day datetime
+);
+
+insert into currencies_new (id, created_at, updated_at, name, rate, last_update_day)
+select id, created_at, updated_at, name, coalesce(rate, 0), last_update_day
+from currencies
+where id in (select min(id) from currencies where name is not null group by name);
+
+create table intervals_new (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    name text not null unique,
+    modifier real not null default 1
+);
+
+insert into intervals_new (id, created_at, updated_at, name, modifier)
+select id, created_at, updated_at, name, coalesce(modifier, 1)
+from intervals
+where id in (select min(id) from intervals where name is not null group by name);
+
+-- currency_id and currencie_id are merged into currencie_id
+create table emails_new (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email text not null unique,
+    currencie_id integer references currencies(id)
+);
+
+insert into emails_new (id, created_at, updated_at, email, currencie_id)
+select e.id, e.created_at, e.updated_at, e.email, (
+    select cn.id from currencies_new cn
+    join currencies c on c.name = cn.name
+    where c.id = coalesce(e.currencie_id, e.currency_id)
+)
+from emails e
+where e.id in (select min(id) from emails where email is not null group by email);
+
+create table subscriptions_new (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    name text not null default '',
+    cost real not null default 0,
+    interval_id integer references intervals(id),
+    interval_amount integer not null default 1,
+    currencie_id integer references currencies(id)
+);
+
+insert into subscriptions_new (
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount, currencie_id
+)
+select * from (
+    select s.id, s.created_at, s.updated_at, (
+        select en.id from emails_new en
+        join emails e on e.email = en.email
+        where e.id = s.email_id
+    ) as new_email_id, coalesce(s.name, ''), coalesce(s.cost, 0), (
+        select iln.id from intervals_new iln
+        join intervals il on il.name = iln.name
+        where il.id = s.interval_id
+    ), coalesce(s.interval_amount, 1), (
+        select cn.id from currencies_new cn
+        join currencies c on c.name = cn.name
+        where c.id = s.currencie_id
+    )
+    from subscriptions s
+)
+where new_email_id is not null;
+
+drop table subscriptions;
+drop table emails;
+drop table intervals;
+drop table currencies;
+
+alter table currencies_new rename to currencies;
+alter table intervals_new rename to intervals;
+alter table emails_new rename to emails;
+alter table subscriptions_new rename to subscriptions;
+
+create index subscriptions_email_id on subscriptions(email_id);
+
+create trigger currencies_ts after insert on currencies
+begin
+    update currencies set updated_at=(datetime('now')) where id=new.id;
+end;
+
+create trigger intervals_ts after insert on intervals
+begin
+    update intervals set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger emails_ts after insert on emails
+begin
+    update emails set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscriptions_ts after insert on subscriptions
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
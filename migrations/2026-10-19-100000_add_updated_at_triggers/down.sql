@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop trigger emails_updated_ts;
+drop trigger subscriptions_updated_ts;
+drop trigger intervals_updated_ts;
+drop trigger currencies_updated_ts;
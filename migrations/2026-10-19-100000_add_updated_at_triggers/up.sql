@@ -0,0 +1,24 @@
+-- Your SQL goes here
+create trigger emails_updated_ts after update on emails
+when new.updated_at is old.updated_at
+begin
+    update emails set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscriptions_updated_ts after update on subscriptions
+when new.updated_at is old.updated_at
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger intervals_updated_ts after update on intervals
+when new.updated_at is old.updated_at
+begin
+    update intervals set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger currencies_updated_ts after update on currencies
+when new.updated_at is old.updated_at
+begin
+    update currencies set updated_at=(datetime('now')) where id=new.id;
+end;
@@ -0,0 +1,42 @@
+-- Your SQL goes here
+create table categories (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    name text not null,
+    unique (email_id, name)
+);
+
+create trigger categories_ts after insert on categories
+begin
+    update categories set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger categories_updated_ts after update on categories
+when new.updated_at is old.updated_at
+begin
+    update categories set updated_at=current_timestamp where id=new.id;
+end;
+
+alter table subscriptions add column category_id integer references categories(id);
+
+create table tags (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    subscription_id integer not null references subscriptions(id),
+    name text not null,
+    unique (subscription_id, name)
+);
+
+create trigger tags_ts after insert on tags
+begin
+    update tags set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger tags_updated_ts after update on tags
+when new.updated_at is old.updated_at
+begin
+    update tags set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+create table spending_snapshots (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    currencie_id integer references currencies(id),
+    snapshot_day datetime not null,
+    monthly_cost real not null,
+    unique (email_id, snapshot_day)
+);
+
+create trigger spending_snapshots_ts after insert on spending_snapshots
+begin
+    update spending_snapshots set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+create table subscription_prices (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    subscription_id integer not null references subscriptions(id),
+    cost real not null,
+    currencie_id integer references currencies(id),
+    interval_id integer references intervals(id),
+    interval_amount integer not null default 1,
+    effective_from datetime not null,
+    effective_to datetime
+);
+
+create index subscription_prices_subscription_id on subscription_prices(subscription_id);
+
+create trigger subscription_prices_ts after insert on subscription_prices
+begin
+    update subscription_prices set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscription_prices_updated_ts after update on subscription_prices
+when new.updated_at is old.updated_at
+begin
+    update subscription_prices set updated_at=current_timestamp where id=new.id;
+end;
+
+insert into subscription_prices (
+    subscription_id, cost, currencie_id, interval_id, interval_amount, effective_from
+)
+select id, cost, currencie_id, interval_id, interval_amount, coalesce(created_at, current_timestamp)
+from subscriptions;
@@ -0,0 +1,38 @@
+-- This file should undo anything in `up.sql`
+create table subscriptions_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    name text not null default '',
+    cost real not null default 0,
+    interval_id integer references intervals(id),
+    interval_amount integer not null default 1,
+    currencie_id integer references currencies(id),
+    category_id integer references categories(id)
+);
+
+insert into subscriptions_old (
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount,
+    currencie_id, category_id
+)
+select
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount,
+    currencie_id, category_id
+from subscriptions;
+
+drop table subscriptions;
+alter table subscriptions_old rename to subscriptions;
+
+create index subscriptions_email_id on subscriptions(email_id);
+
+create trigger subscriptions_ts after insert on subscriptions
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscriptions_updated_ts after update on subscriptions
+when new.updated_at is old.updated_at
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+alter table subscriptions add column trial_ends_at datetime;
+alter table subscriptions add column promo_cost real;
+alter table subscriptions add column promo_months integer;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+alter table subscriptions add column status text not null default 'active';
+alter table subscriptions add column paused_until datetime;
+alter table subscriptions add column cancelled_at datetime;
+alter table subscriptions add column status_changed_at datetime;
+
+create index subscriptions_status on subscriptions(status);
@@ -0,0 +1,52 @@
+-- Your SQL goes here
+create table households (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    name text not null,
+    currencie_id integer references currencies(id)
+);
+
+create trigger households_ts after insert on households
+begin
+    update households set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger households_updated_ts after update on households
+when new.updated_at is old.updated_at
+begin
+    update households set updated_at=current_timestamp where id=new.id;
+end;
+
+create table household_members (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    household_id integer not null references households(id),
+    email_id integer not null references emails(id),
+    unique (household_id, email_id)
+);
+
+create trigger household_members_ts after insert on household_members
+begin
+    update household_members set updated_at=current_timestamp where id=new.id;
+end;
+
+alter table subscriptions add column household_id integer references households(id);
+
+-- Either a ratio of what's left after fixed shares, or a fixed amount per billing interval
+create table subscription_shares (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    subscription_id integer not null references subscriptions(id),
+    email_id integer not null references emails(id),
+    ratio real,
+    fixed_amount real,
+    unique (subscription_id, email_id)
+);
+
+create trigger subscription_shares_ts after insert on subscription_shares
+begin
+    update subscription_shares set updated_at=current_timestamp where id=new.id;
+end;
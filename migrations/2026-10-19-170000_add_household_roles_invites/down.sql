@@ -0,0 +1,22 @@
+-- This file should undo anything in `up.sql`
+drop table household_invites;
+
+create table household_members_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    household_id integer not null references households(id),
+    email_id integer not null references emails(id),
+    unique (household_id, email_id)
+);
+
+insert into household_members_old (id, created_at, updated_at, household_id, email_id)
+select id, created_at, updated_at, household_id, email_id from household_members;
+
+drop table household_members;
+alter table household_members_old rename to household_members;
+
+create trigger household_members_ts after insert on household_members
+begin
+    update household_members set updated_at=current_timestamp where id=new.id;
+end;
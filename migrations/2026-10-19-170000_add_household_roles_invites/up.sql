@@ -0,0 +1,22 @@
+-- Your SQL goes here
+alter table household_members add column role text not null default 'editor';
+
+-- Households so far were created by their first member
+update household_members set role='owner'
+where id in (select min(id) from household_members group by household_id);
+
+create table household_invites (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    household_id integer not null references households(id),
+    email text not null,
+    role text not null,
+    invited_by integer references emails(id),
+    unique (household_id, email)
+);
+
+create trigger household_invites_ts after insert on household_invites
+begin
+    update household_invites set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,44 @@
+-- Your SQL goes here
+-- A monthly limit in the email's currency, over everything or a single category
+create table budgets (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    category_id integer references categories(id),
+    monthly_limit real not null,
+    is_over boolean not null default 0
+);
+
+create unique index budgets_email_category on budgets(email_id, ifnull(category_id, 0));
+
+create trigger budgets_ts after insert on budgets
+begin
+    update budgets set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger budgets_updated_ts after update on budgets
+when new.updated_at is old.updated_at
+begin
+    update budgets set updated_at=current_timestamp where id=new.id;
+end;
+
+-- Every time spending crossed a budget, either way
+create table budget_alerts (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    budget_id integer not null references budgets(id),
+    email_id integer not null references emails(id),
+    monthly_limit real not null,
+    spent real not null,
+    is_over boolean not null,
+    source text not null
+);
+
+create index budget_alerts_email_id on budget_alerts(email_id);
+
+create trigger budget_alerts_ts after insert on budget_alerts
+begin
+    update budget_alerts set updated_at=current_timestamp where id=new.id;
+end;
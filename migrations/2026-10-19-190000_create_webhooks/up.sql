@@ -0,0 +1,54 @@
+-- Your SQL goes here
+-- event_types is a comma separated filter, empty for every event
+create table webhooks (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    url text not null,
+    secret text not null,
+    event_types text not null default '',
+    active boolean not null default 1
+);
+
+create trigger webhooks_ts after insert on webhooks
+begin
+    update webhooks set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger webhooks_updated_ts after update on webhooks
+when new.updated_at is old.updated_at
+begin
+    update webhooks set updated_at=current_timestamp where id=new.id;
+end;
+
+-- event_key keeps scheduled events like renewals from being sent twice
+create table webhook_deliveries (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    webhook_id integer not null references webhooks(id),
+    event_type text not null,
+    event_key text,
+    payload text not null,
+    status text not null default 'pending',
+    attempts integer not null default 0,
+    next_attempt_at datetime,
+    response_status integer,
+    last_error text,
+    delivered_at datetime,
+    unique (webhook_id, event_key)
+);
+
+create index webhook_deliveries_status on webhook_deliveries(status, next_attempt_at);
+
+create trigger webhook_deliveries_ts after insert on webhook_deliveries
+begin
+    update webhook_deliveries set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger webhook_deliveries_updated_ts after update on webhook_deliveries
+when new.updated_at is old.updated_at
+begin
+    update webhook_deliveries set updated_at=current_timestamp where id=new.id;
+end;
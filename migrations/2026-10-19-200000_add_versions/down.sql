@@ -0,0 +1,74 @@
+-- This file should undo anything in `up.sql`
+create table subscriptions_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    name text not null default '',
+    cost real not null default 0,
+    interval_id integer references intervals(id),
+    interval_amount integer not null default 1,
+    currencie_id integer references currencies(id),
+    category_id integer references categories(id),
+    trial_ends_at datetime,
+    promo_cost real,
+    promo_months integer,
+    status text not null default 'active',
+    paused_until datetime,
+    cancelled_at datetime,
+    status_changed_at datetime,
+    household_id integer references households(id)
+);
+
+insert into subscriptions_old (
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount,
+    currencie_id, category_id, trial_ends_at, promo_cost, promo_months, status, paused_until,
+    cancelled_at, status_changed_at, household_id
+)
+select
+    id, created_at, updated_at, email_id, name, cost, interval_id, interval_amount,
+    currencie_id, category_id, trial_ends_at, promo_cost, promo_months, status, paused_until,
+    cancelled_at, status_changed_at, household_id
+from subscriptions;
+
+drop table subscriptions;
+alter table subscriptions_old rename to subscriptions;
+
+create index subscriptions_email_id on subscriptions(email_id);
+create index subscriptions_status on subscriptions(status);
+
+create trigger subscriptions_ts after insert on subscriptions
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger subscriptions_updated_ts after update on subscriptions
+when new.updated_at is old.updated_at
+begin
+    update subscriptions set updated_at=current_timestamp where id=new.id;
+end;
+
+create table emails_old (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email text not null unique,
+    currencie_id integer references currencies(id)
+);
+
+insert into emails_old (id, created_at, updated_at, email, currencie_id)
+select id, created_at, updated_at, email, currencie_id from emails;
+
+drop table emails;
+alter table emails_old rename to emails;
+
+create trigger emails_ts after insert on emails
+begin
+    update emails set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger emails_updated_ts after update on emails
+when new.updated_at is old.updated_at
+begin
+    update emails set updated_at=current_timestamp where id=new.id;
+end;
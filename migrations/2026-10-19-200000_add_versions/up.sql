@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Rows are inserted at 0, the insert triggers' updated_at touch brings them to 1
+alter table emails add column version integer not null default 0;
+alter table subscriptions add column version integer not null default 0;
+
+-- Every update bumps the version, whatever version it wrote
+drop trigger emails_updated_ts;
+create trigger emails_updated_ts after update on emails
+when new.version <= old.version
+begin
+    update emails set updated_at=current_timestamp, version=old.version + 1 where id=new.id;
+end;
+
+drop trigger subscriptions_updated_ts;
+create trigger subscriptions_updated_ts after update on subscriptions
+when new.version <= old.version
+begin
+    update subscriptions set updated_at=current_timestamp, version=old.version + 1 where id=new.id;
+end;
+
+update emails set version=1;
+update subscriptions set version=1;
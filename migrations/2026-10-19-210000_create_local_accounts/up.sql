@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- Password logins for self-hosted installs, password_hash is an argon2 PHC string
+create table local_accounts (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null unique references emails(id),
+    password_hash text not null
+);
+
+create trigger local_accounts_ts after insert on local_accounts
+begin
+    update local_accounts set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger local_accounts_updated_ts after update on local_accounts
+when new.updated_at is old.updated_at
+begin
+    update local_accounts set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- Signed in devices. Only a hash of the refresh token is kept, and revoked_at ends the
+-- session along with its access tokens.
+create table sessions (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email text not null,
+    refresh_token_hash text not null unique,
+    expires_at datetime not null,
+    revoked_at datetime
+);
+
+create index sessions_email on sessions(email);
+
+create trigger sessions_ts after insert on sessions
+begin
+    update sessions set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger sessions_updated_ts after update on sessions
+when new.updated_at is old.updated_at
+begin
+    update sessions set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- Personal keys for scripts. Only a hash of the key is kept, prefix is its first characters
+-- so keys can be told apart.
+create table api_keys (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    name text not null,
+    prefix text not null,
+    key_hash text not null unique,
+    read_only boolean not null default 0,
+    expires_at datetime,
+    last_used_at datetime
+);
+
+create trigger api_keys_ts after insert on api_keys
+begin
+    update api_keys set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger api_keys_updated_ts after update on api_keys
+when new.updated_at is old.updated_at
+begin
+    update api_keys set updated_at=current_timestamp where id=new.id;
+end;
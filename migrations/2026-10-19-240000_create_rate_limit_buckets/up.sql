@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Rate limit buckets that weren't full, saved so a restart doesn't reset them when
+-- rate_limits.persist is on. refilled_at is in unix seconds.
+create table rate_limit_buckets (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    bucket_key text not null unique,
+    tokens double not null,
+    refilled_at double not null
+);
+
+create trigger rate_limit_buckets_ts after insert on rate_limit_buckets
+begin
+    update rate_limit_buckets set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger rate_limit_buckets_updated_ts after update on rate_limit_buckets
+when new.updated_at is old.updated_at
+begin
+    update rate_limit_buckets set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- Who changed what. email_id is the email the changed row belongs to, null for changes that
+-- aren't anyone's, and isn't a foreign key so the log outlives deleted emails.
+create table audit_logs (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer,
+    actor text not null,
+    action text not null,
+    entity text not null,
+    entity_id integer,
+    before_json text,
+    after_json text,
+    request_id text
+);
+
+create index audit_logs_email_id on audit_logs(email_id);
+
+create trigger audit_logs_ts after insert on audit_logs
+begin
+    update audit_logs set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger audit_logs_updated_ts after update on audit_logs
+when new.updated_at is old.updated_at
+begin
+    update audit_logs set updated_at=current_timestamp where id=new.id;
+end;
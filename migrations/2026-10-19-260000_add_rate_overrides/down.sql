@@ -0,0 +1,28 @@
+-- This file should undo anything in `up.sql`
+drop table rate_overrides;
+
+create table currencies_old (
+    id integer primary key autoincrement,
+    created_at datetime default (datetime('now')),
+    updated_at datetime default (datetime('now')),
+    name text not null unique,
+    rate real not null default 0,
+    last_update_day datetime
+);
+
+insert into currencies_old (id, created_at, updated_at, name, rate, last_update_day)
+select id, created_at, updated_at, name, rate, last_update_day from currencies;
+
+drop table currencies;
+alter table currencies_old rename to currencies;
+
+create trigger currencies_ts after insert on currencies
+begin
+    update currencies set updated_at=(datetime('now')) where id=new.id;
+end;
+
+create trigger currencies_updated_ts after update on currencies
+when new.updated_at is old.updated_at
+begin
+    update currencies set updated_at=(datetime('now')) where id=new.id;
+end;
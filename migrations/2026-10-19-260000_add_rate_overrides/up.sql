@@ -0,0 +1,31 @@
+-- Your SQL goes here
+-- rate stays the one every conversion uses. manual_rate pins it over the provider's, which is
+-- still kept in provider_rate, and custom currencies aren't from the provider at all.
+alter table currencies add column manual_rate real;
+alter table currencies add column provider_rate real;
+alter table currencies add column custom boolean not null default 0;
+
+update currencies set provider_rate=rate where rate > 0;
+
+-- An email's own rate for a currency, used instead of the shared one in its costs
+create table rate_overrides (
+    id integer primary key autoincrement,
+    created_at datetime default current_timestamp,
+    updated_at datetime default current_timestamp,
+    email_id integer not null references emails(id),
+    currencie_id integer not null references currencies(id),
+    rate real not null
+);
+
+create unique index rate_overrides_email_currencie on rate_overrides(email_id, currencie_id);
+
+create trigger rate_overrides_ts after insert on rate_overrides
+begin
+    update rate_overrides set updated_at=current_timestamp where id=new.id;
+end;
+
+create trigger rate_overrides_updated_ts after update on rate_overrides
+when new.updated_at is old.updated_at
+begin
+    update rate_overrides set updated_at=current_timestamp where id=new.id;
+end;
@@ -0,0 +1,129 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use diesel::prelude::*;
+
+use crate::household;
+use crate::model::{Email, LocalAccount};
+
+// Username and password logins for self-hosted installs without internet. Accounts are
+// created from the command line, and a login starts a session like any other.
+
+const INVALID_LOGIN: &str = "Invalid username or password";
+
+#[derive(Debug, Deserialize)]
+pub struct LoginBody {
+    pub username: String,
+    pub password: String,
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+pub fn check_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// Creates the account, and its email when new, or changes its password
+pub fn set_password(conn: &SqliteConnection, username: &str, password: &str) -> Result<(), String> {
+    if password.is_empty() {
+        return Err(String::from("Password can't be empty"));
+    }
+
+    let password_hash = hash_password(password)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let found_email_id = match household::email_id_of(conn, username)? {
+            Some(found_email_id) => found_email_id,
+            None => {
+                use crate::schema::emails::dsl::*;
+
+                Email {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    email: username.trim().to_string(),
+                    currencie_id: None,
+                    version: 0,
+                }
+                .save(conn)?;
+
+                emails
+                    .select(id)
+                    .order(id.desc())
+                    .first::<Option<i32>>(conn)?
+                    .unwrap_or(0)
+            }
+        };
+
+        let account_id = {
+            use crate::schema::local_accounts::dsl::*;
+            local_accounts
+                .filter(email_id.eq(found_email_id))
+                .select(id)
+                .first::<Option<i32>>(conn)
+                .optional()?
+                .flatten()
+        };
+
+        LocalAccount {
+            id: account_id,
+            created_at: None,
+            updated_at: None,
+            email_id: found_email_id,
+            password_hash,
+        }
+        .save(conn)
+        .map(|_| ())
+    })
+    .map_err(|e| e.to_string())
+}
+
+// The account's email when the password is right
+pub fn login(conn: &SqliteConnection, username: &str, password: &str) -> Result<String, String> {
+    use crate::schema::emails;
+    use crate::schema::local_accounts;
+
+    let found_email_id = household::email_id_of(conn, username).map_err(|e| e.to_string())?;
+
+    let found = local_accounts::table
+        .inner_join(emails::table)
+        .filter(emails::id.nullable().eq(found_email_id))
+        .select((emails::email, local_accounts::password_hash))
+        .first::<(String, String)>(conn)
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    match found {
+        Some((email, password_hash)) if check_password(password, &password_hash) => Ok(email),
+        _ => Err(String::from(INVALID_LOGIN)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_passwords() {
+        let password_hash = hash_password("hunter2").unwrap();
+
+        assert!(password_hash.starts_with("$argon2"));
+        assert!(check_password("hunter2", &password_hash));
+        assert!(!check_password("hunter3", &password_hash));
+        assert!(!check_password("hunter2", "not a hash"));
+    }
+}
@@ -0,0 +1,125 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::model::ApiKey;
+use crate::price;
+
+// Personal keys for scripts that can't sign in, sent instead of an id token. The key is only
+// shown when created, we keep its SHA-256 which is enough for 256 random bits.
+
+// Every key starts with this, so it's told apart from id tokens
+pub const PREFIX: &str = "monty_";
+// How much of the key is kept to recognize it
+const SHOWN_CHARS: usize = 14;
+// Ten years, longer ones should never expire instead
+pub const MAX_EXPIRES_IN_DAYS: i64 = 3650;
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyBody {
+    pub name: String,
+    #[serde(default)]
+    pub read_only: bool,
+    // Never expires when not set
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(PREFIX)
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn create(
+    conn: &SqliteConnection,
+    owner_id: i32,
+    api_key_body: ApiKeyBody,
+) -> QueryResult<CreatedApiKey> {
+    use crate::schema::api_keys::dsl::*;
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let key = format!("{}{}", PREFIX, hex::encode(bytes));
+
+    conn.transaction(|| {
+        diesel::insert_into(api_keys)
+            .values(ApiKey {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                email_id: owner_id,
+                name: api_key_body.name,
+                prefix: key.chars().take(SHOWN_CHARS).collect(),
+                key_hash: hash_key(&key),
+                read_only: api_key_body.read_only,
+                expires_at: api_key_body
+                    .expires_in_days
+                    .map(|days| (Utc::now() + Duration::days(days)).naive_utc()),
+                last_used_at: None,
+            })
+            .execute(conn)?;
+
+        let saved_id = diesel::select(price::last_insert_rowid).get_result::<i32>(conn)?;
+
+        Ok(CreatedApiKey {
+            key,
+            api_key: api_keys.find(saved_id).first::<ApiKey>(conn)?,
+        })
+    })
+}
+
+pub fn api_keys_of(conn: &SqliteConnection, owner_id: i32) -> QueryResult<Vec<ApiKey>> {
+    use crate::schema::api_keys::dsl::*;
+
+    api_keys
+        .filter(email_id.eq(owner_id))
+        .order(id.desc())
+        .load::<ApiKey>(conn)
+}
+
+pub fn delete(conn: &SqliteConnection, api_key_id: i32) -> QueryResult<usize> {
+    use crate::schema::api_keys::dsl::*;
+
+    diesel::delete(api_keys.find(api_key_id)).execute(conn)
+}
+
+// The owner's email and whether the key is read only, for a key that exists and hasn't expired
+pub fn verify(conn: &SqliteConnection, key: &str) -> QueryResult<Option<(String, bool)>> {
+    use crate::schema::api_keys;
+    use crate::schema::emails;
+
+    let now = Utc::now().naive_utc();
+
+    let found = api_keys::table
+        .inner_join(emails::table)
+        .filter(api_keys::key_hash.eq(hash_key(key)))
+        .filter(
+            api_keys::expires_at
+                .is_null()
+                .or(api_keys::expires_at.gt(now)),
+        )
+        .select((api_keys::id, emails::email, api_keys::read_only))
+        .first::<(Option<i32>, String, bool)>(conn)
+        .optional()?;
+
+    match found {
+        Some((found_id, email, read_only)) => {
+            diesel::update(api_keys::table.filter(api_keys::id.eq(found_id)))
+                .set(api_keys::last_used_at.eq(now))
+                .execute(conn)?;
+
+            Ok(Some((email, read_only)))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,182 @@
+// The frontend. Releases embed frontend/dist into the binary with the embed-frontend feature,
+// so it runs from any directory, otherwise it's served from ./frontend for development.
+
+#[cfg(not(feature = "embed-frontend"))]
+pub use dev::{configure, init, is_asset};
+
+#[cfg(not(feature = "embed-frontend"))]
+mod dev {
+    use actix_web::web;
+
+    const ASSETS: [&str; 5] = ["/", "/script.js", "/main.js", "/diesel.png", "/paypal.webp"];
+
+    pub fn init(_base_url: &str) {}
+
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.service(actix_files::Files::new("/", "./frontend").index_file("index.html"));
+    }
+
+    // Frontend files, which anyone may load
+    pub fn is_asset(path: &str) -> bool {
+        ASSETS.contains(&path)
+    }
+}
+
+#[cfg(feature = "embed-frontend")]
+pub use embedded::{configure, init, is_asset};
+
+#[cfg(feature = "embed-frontend")]
+mod embedded {
+    use actix_web::{web, web::Bytes, HttpRequest, HttpResponse};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::sync::OnceLock;
+
+    // FILES, the (name, contents) of every file in frontend/dist, generated by build.rs
+    include!(concat!(env!("OUT_DIR"), "/frontend.rs"));
+
+    const INDEX: &str = "index.html";
+    // index.html carries the API url here, replaced with base_url from env.json
+    const BASE_URL_META: &str = "<meta name=\"monty-base-url\" content=\"";
+
+    struct Asset {
+        content_type: String,
+        body: Bytes,
+        hash: String,
+        // Served under its hashed name, so it can be cached for good
+        immutable: bool,
+    }
+
+    static ASSETS: OnceLock<HashMap<String, Asset>> = OnceLock::new();
+
+    fn content_hash(contents: &[u8]) -> String {
+        hex::encode(&Sha256::digest(contents)[..8])
+    }
+
+    // main.js -> main.<hash>.js
+    fn hashed_name(name: &str, hash: &str) -> String {
+        match name.rfind('.') {
+            Some(dot) => format!("{}.{}{}", &name[..dot], hash, &name[dot..]),
+            None => format!("{}.{}", name, hash),
+        }
+    }
+
+    fn content_type(name: &str) -> String {
+        let extension = name.rsplit('.').next().unwrap_or("");
+        actix_files::file_extension_to_mime(extension).to_string()
+    }
+
+    fn inject_base_url(index: &str, base_url: &str) -> String {
+        let start = match index.find(BASE_URL_META) {
+            Some(start) => start + BASE_URL_META.len(),
+            None => return index.to_string(),
+        };
+        let end = start + index[start..].find('"').unwrap_or(0);
+
+        let escaped = base_url
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;");
+
+        format!("{}{}{}", &index[..start], escaped, &index[end..])
+    }
+
+    // Hashes the embedded files and points index.html at the hashed names
+    pub fn init(base_url: &str) {
+        let mut assets = HashMap::new();
+        let mut index = String::new();
+        let mut renames = vec![];
+
+        for (name, contents) in FILES.iter() {
+            if *name == INDEX {
+                index = String::from_utf8_lossy(contents).into_owned();
+                continue;
+            }
+
+            let hash = content_hash(contents);
+            let hashed = hashed_name(name, &hash);
+
+            for (path, immutable) in [
+                (format!("/{}", name), false),
+                (format!("/{}", hashed), true),
+            ] {
+                assets.insert(
+                    path,
+                    Asset {
+                        content_type: content_type(name),
+                        body: Bytes::from_static(contents),
+                        hash: hash.clone(),
+                        immutable,
+                    },
+                );
+            }
+
+            renames.push((name, hashed));
+        }
+
+        for (name, hashed) in renames {
+            index = index
+                .replace(&format!("\"{}\"", name), &format!("\"{}\"", hashed))
+                .replace(&format!("\"/{}\"", name), &format!("\"/{}\"", hashed));
+        }
+
+        if !base_url.is_empty() {
+            index = inject_base_url(&index, base_url);
+        }
+
+        let index_asset = || Asset {
+            content_type: content_type(INDEX),
+            hash: content_hash(index.as_bytes()),
+            body: Bytes::from(index.clone()),
+            immutable: false,
+        };
+        assets.insert(String::from("/"), index_asset());
+        assets.insert(format!("/{}", INDEX), index_asset());
+
+        let _ = ASSETS.set(assets);
+    }
+
+    pub fn is_asset(path: &str) -> bool {
+        ASSETS
+            .get()
+            .map(|assets| assets.contains_key(path))
+            .unwrap_or(false)
+    }
+
+    async fn asset(req: HttpRequest) -> HttpResponse {
+        let asset = match ASSETS.get().and_then(|assets| assets.get(req.path())) {
+            Some(asset) => asset,
+            None => return HttpResponse::NotFound().finish(),
+        };
+
+        let etag = format!("\"{}\"", asset.hash);
+
+        let not_modified = req
+            .headers()
+            .get("if-none-match")
+            .and_then(|header| header.to_str().ok())
+            .map(|header| header.split(',').any(|tag| tag.trim() == etag))
+            .unwrap_or(false);
+
+        if not_modified {
+            return HttpResponse::NotModified().header("etag", etag).finish();
+        }
+
+        HttpResponse::Ok()
+            .content_type(asset.content_type.as_str())
+            .header(
+                "cache-control",
+                if asset.immutable {
+                    "public, max-age=31536000, immutable"
+                } else {
+                    "no-cache"
+                },
+            )
+            .header("etag", etag)
+            .body(asset.body.clone())
+    }
+
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.route("/{path:.*}", web::get().to(asset));
+    }
+}
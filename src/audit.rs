@@ -0,0 +1,346 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, NaiveDate};
+use diesel::prelude::*;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde::Serialize;
+use serde_json::Value;
+use std::task::{Context, Poll};
+
+use crate::auth::AuthEmail;
+use crate::model::AuditLog;
+
+// Every create, update and delete of emails, subscriptions, budgets and rate overrides, with
+// who made it and the row before and after as JSON. Written in the same transaction as the
+// change where there is one.
+
+pub const CREATE: &str = "create";
+pub const UPDATE: &str = "update";
+pub const DELETE: &str = "delete";
+
+pub const EMAIL: &str = "email";
+pub const SUBSCRIPTION: &str = "subscription";
+pub const BUDGET: &str = "budget";
+// Pinned and custom currency rates, and emails' own rates
+pub const RATE: &str = "rate";
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longest request id taken from the client
+const MAX_REQUEST_ID: usize = 64;
+// Most entries returned at once
+const MAX_LIMIT: i64 = 500;
+
+// Set on every request and returned as X-Request-Id
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// Who made a change, the signed in email or "cli"
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub email: String,
+    pub request_id: Option<String>,
+}
+
+impl Actor {
+    pub fn cli() -> Actor {
+        Actor {
+            email: String::from("cli"),
+            request_id: None,
+        }
+    }
+}
+
+impl FromRequest for Actor {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|request_id| request_id.0.clone());
+        let auth_email = AuthEmail::from_request(req, payload);
+
+        Box::pin(async move {
+            Ok(Actor {
+                email: auth_email.await?.0,
+                request_id,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub entity: Option<String>,
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub limit: Option<i64>,
+}
+
+fn to_json<T: Serialize>(row: Option<&T>) -> Option<Value> {
+    row.and_then(|row| serde_json::to_value(row).ok())
+}
+
+// Logs the change of a row belonging to email_id. No before is a create, no after a delete,
+// and updates that changed nothing aren't logged.
+pub fn record<T: Serialize>(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    entity: &str,
+    email_id: Option<i32>,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> QueryResult<()> {
+    use crate::schema::audit_logs::dsl;
+
+    let before = to_json(before);
+    let after = to_json(after);
+
+    let action = match (&before, &after) {
+        (None, None) => return Ok(()),
+        (Some(before), Some(after)) if before == after => return Ok(()),
+        (None, Some(_)) => CREATE,
+        (Some(_), None) => DELETE,
+        (Some(_), Some(_)) => UPDATE,
+    };
+    let entity_id = after
+        .as_ref()
+        .or(before.as_ref())
+        .and_then(|row| row["id"].as_i64())
+        .map(|id| id as i32);
+
+    diesel::insert_into(dsl::audit_logs)
+        .values(AuditLog {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            email_id,
+            actor: actor.email.clone(),
+            action: String::from(action),
+            entity: String::from(entity),
+            entity_id,
+            before_json: before.map(|before| before.to_string()),
+            after_json: after.map(|after| after.to_string()),
+            request_id: actor.request_id.clone(),
+        })
+        .execute(conn)
+        .map(|_| ())
+}
+
+// Newest first
+pub fn logs_of(
+    conn: &SqliteConnection,
+    owner_id: i32,
+    audit_query: &AuditQuery,
+) -> QueryResult<Vec<AuditLog>> {
+    use crate::schema::audit_logs::dsl;
+
+    let mut query = dsl::audit_logs
+        .filter(dsl::email_id.eq(owner_id))
+        .into_boxed();
+
+    if let Some(entity) = &audit_query.entity {
+        query = query.filter(dsl::entity.eq(entity));
+    }
+    if let Some(action) = &audit_query.action {
+        query = query.filter(dsl::action.eq(action));
+    }
+    if let Some(actor) = &audit_query.actor {
+        query = query.filter(dsl::actor.eq(actor));
+    }
+    if let Some(from) = audit_query.from {
+        query = query.filter(dsl::created_at.ge(from.and_hms(0, 0, 0)));
+    }
+    if let Some(to) = audit_query.to {
+        query = query.filter(dsl::created_at.lt((to + Duration::days(1)).and_hms(0, 0, 0)));
+    }
+
+    query
+        .order(dsl::id.desc())
+        .limit(audit_query.limit.unwrap_or(100).clamp(1, MAX_LIMIT))
+        .load::<AuditLog>(conn)
+}
+
+// The client's X-Request-Id when it looks like one, otherwise a new one
+fn request_id_of(req: &ServiceRequest) -> String {
+    let sent = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|val| val.to_str().ok())
+        .filter(|sent| {
+            !sent.is_empty()
+                && sent.len() <= MAX_REQUEST_ID
+                && sent
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+
+    match sent {
+        Some(sent) => sent.to_string(),
+        None => {
+            let mut bytes = [0u8; 16];
+            OsRng.fill_bytes(&mut bytes);
+            hex::encode(bytes)
+        }
+    }
+}
+
+// Gives every request an id for the audit log and the response
+pub struct RequestIds;
+
+impl<S, B> Transform<S> for RequestIds
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdsMiddleware { service })
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestIdsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = request_id_of(&req);
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            if let Ok(val) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), val);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Budget;
+    use crate::{establish_pool, run_migrations};
+
+    fn budget(monthly_limit: f32) -> Budget {
+        Budget {
+            id: Some(7),
+            created_at: None,
+            updated_at: None,
+            email_id: 1,
+            category_id: None,
+            monthly_limit,
+            is_over: false,
+        }
+    }
+
+    #[test]
+    fn records_and_filters_changes() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+
+        let actor = Actor {
+            email: String::from("a@x.com"),
+            request_id: Some(String::from("req-1")),
+        };
+
+        record(&conn, &actor, BUDGET, Some(1), None, Some(&budget(10.0))).unwrap();
+        record(
+            &conn,
+            &actor,
+            BUDGET,
+            Some(1),
+            Some(&budget(10.0)),
+            Some(&budget(20.0)),
+        )
+        .unwrap();
+        // Nothing changed
+        record(
+            &conn,
+            &actor,
+            BUDGET,
+            Some(1),
+            Some(&budget(20.0)),
+            Some(&budget(20.0)),
+        )
+        .unwrap();
+        record(
+            &conn,
+            &Actor::cli(),
+            BUDGET,
+            Some(1),
+            Some(&budget(20.0)),
+            None,
+        )
+        .unwrap();
+        record(&conn, &actor, BUDGET, Some(2), None, Some(&budget(5.0))).unwrap();
+
+        let logs = logs_of(&conn, 1, &AuditQuery::default()).unwrap();
+        let actions: Vec<&str> = logs.iter().map(|log| log.action.as_str()).collect();
+        assert_eq!(actions, vec![DELETE, UPDATE, CREATE]);
+        assert_eq!(logs[1].entity_id, Some(7));
+        assert_eq!(logs[1].request_id, Some(String::from("req-1")));
+        assert!(logs[0].after_json.is_none());
+
+        let by_cli = logs_of(
+            &conn,
+            1,
+            &AuditQuery {
+                actor: Some(String::from("cli")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(by_cli.len(), 1);
+
+        let updates = logs_of(
+            &conn,
+            1,
+            &AuditQuery {
+                action: Some(String::from(UPDATE)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let after: Value = serde_json::from_str(updates[0].after_json.as_ref().unwrap()).unwrap();
+        assert_eq!(after["monthly_limit"], 20.0);
+    }
+}
@@ -0,0 +1,279 @@
+use actix_web::{
+    dev::Payload,
+    error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized},
+    http::{HeaderMap, Method, StatusCode},
+    web, Error, FromRequest, HttpRequest,
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::Duration;
+use futures::future::LocalBoxFuture;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::{
+    api_key,
+    config::Config,
+    oidc,
+    session::{self, Lifetimes},
+    DbPool,
+};
+
+const ACCESS_TOKEN_MINUTES: i64 = 60;
+const REFRESH_TOKEN_DAYS: i64 = 30;
+// The one in frontend/index.html
+const GOOGLE_CLIENT_ID: &str =
+    "354857779698-4l5m51k5gcih8h5e2733s10hm504kk2u.apps.googleusercontent.com";
+
+pub const READ_ONLY: &str = "Read-only API key";
+// The only route taking ?access_token=, for EventSource. Elsewhere it would end up in logs.
+const QUERY_TOKEN_PATH: &str = "/events";
+
+// How sign ins are verified and sessions issued, set from env.json when the server starts
+pub struct Settings {
+    pub google_client_id: String,
+    pub oidc: Vec<oidc::Provider>,
+    pub local_accounts: bool,
+    pub session_secret: Vec<u8>,
+    pub lifetimes: Lifetimes,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+impl Settings {
+    fn from_config(config: &Config) -> Settings {
+        let session_secret = if config.session_secret.is_empty() {
+            println!("No session_secret set, sessions won't survive a restart.");
+            let mut session_secret = vec![0u8; 32];
+            OsRng.fill_bytes(&mut session_secret);
+            session_secret
+        } else {
+            config.session_secret.as_bytes().to_vec()
+        };
+
+        let or_default = |configured: i64, default: i64| {
+            if configured > 0 {
+                configured
+            } else {
+                default
+            }
+        };
+
+        let google_client_id = if config.google_client_id.is_empty() {
+            String::from(GOOGLE_CLIENT_ID)
+        } else {
+            config.google_client_id.clone()
+        };
+
+        Settings {
+            google_client_id,
+            oidc: config.oidc_providers.clone(),
+            local_accounts: config.local_accounts,
+            session_secret,
+            lifetimes: Lifetimes {
+                access: Duration::minutes(or_default(
+                    config.access_token_minutes,
+                    ACCESS_TOKEN_MINUTES,
+                )),
+                refresh: Duration::days(or_default(config.refresh_token_days, REFRESH_TOKEN_DAYS)),
+            },
+        }
+    }
+}
+
+pub fn init(config: &Config) {
+    let _ = SETTINGS.set(Settings::from_config(config));
+}
+
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings::from_config(&Config::default()))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenInfo {
+    email: String,
+    aud: String,
+    // Sent as a string by tokeninfo
+    #[serde(default)]
+    email_verified: Value,
+}
+
+impl TokenInfo {
+    // Tokens Google issued to other apps, or for unverified emails, aren't ours to trust
+    fn email_for(self, google_client_id: &str) -> Option<String> {
+        let verified = self.email_verified == Value::Bool(true) || self.email_verified == "true";
+
+        if self.aud == google_client_id && verified {
+            Some(self.email)
+        } else {
+            println!("Token invalid! Issued to another client or for an unverified email");
+            None
+        }
+    }
+}
+
+// The Google account email an id token was issued for, if the token is valid
+pub async fn google_email(id_token: &str) -> Option<String> {
+    let url = format!(
+        "https://oauth2.googleapis.com/tokeninfo?id_token={}",
+        id_token
+    );
+
+    match reqwest::get(url.as_str()).await {
+        Ok(resp) if resp.status() == StatusCode::OK => resp
+            .json::<TokenInfo>()
+            .await
+            .ok()
+            .and_then(|token_info| token_info.email_for(&settings().google_client_id)),
+        Ok(resp) => {
+            println!("Token invalid! {}", resp.status());
+            None
+        }
+        _ => None,
+    }
+}
+
+// The email any token we accept was issued for: our own session access tokens, which need a
+// pool to check the session wasn't revoked, or id tokens from a configured OpenID Connect
+// provider or Google
+pub async fn verify(pool: Option<&DbPool>, id_token: &str) -> Option<String> {
+    let settings = settings();
+
+    match oidc::issuer(id_token) {
+        Some(issuer) if issuer == session::ISSUER => {
+            let (email, session_id) =
+                session::verify_access_token(&settings.session_secret, id_token)?;
+            let conn = pool?.get().ok()?;
+
+            match web::block(move || session::is_active(&conn, session_id)).await {
+                Ok(true) => Some(email),
+                _ => None,
+            }
+        }
+        Some(issuer) => match oidc::provider_for(&settings.oidc, &issuer) {
+            Some(provider) => match oidc::verify(provider, id_token).await {
+                Ok(email) => Some(email),
+                Err(e) => {
+                    println!("Token invalid! {}", e);
+                    None
+                }
+            },
+            None => google_email(id_token).await,
+        },
+        None => google_email(id_token).await,
+    }
+}
+
+// Who a request is from, and whether they may only read
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub email: String,
+    pub read_only: bool,
+}
+
+impl Identity {
+    pub fn may(&self, method: &Method) -> bool {
+        !self.read_only || [Method::GET, Method::HEAD, Method::OPTIONS].contains(method)
+    }
+}
+
+// Like verify, also accepting personal API keys, which can be read only
+pub async fn identify(pool: Option<&DbPool>, token: &str) -> Option<Identity> {
+    if api_key::is_api_key(token) {
+        let conn = pool?.get().ok()?;
+        let key = token.to_string();
+
+        return match web::block(move || api_key::verify(&conn, &key)).await {
+            Ok(Some((email, read_only))) => Some(Identity { email, read_only }),
+            _ => None,
+        };
+    }
+
+    verify(pool, token).await.map(|email| Identity {
+        email,
+        read_only: false,
+    })
+}
+
+// The id token from the authorization header, an API key from X-Api-Key, or for GET /events
+// the access_token query parameter, as EventSource can't set headers
+pub fn id_token(headers: &HeaderMap, path: &str, query_string: &str) -> Option<String> {
+    headers
+        .get("authorization")
+        .or_else(|| headers.get("x-api-key"))
+        .and_then(|auth| auth.to_str().ok())
+        .map(String::from)
+        .or_else(|| {
+            if path != QUERY_TOKEN_PATH {
+                return None;
+            }
+
+            web::Query::<HashMap<String, String>>::from_query(query_string)
+                .ok()
+                .and_then(|query| query.get("access_token").cloned())
+        })
+}
+
+// The signed in email, verified from the request's id token
+#[derive(Debug, Clone)]
+pub struct AuthEmail(pub String);
+
+impl FromRequest for AuthEmail {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // Already verified by the policy middleware
+        if let Some(auth_email) = req.extensions().get::<AuthEmail>() {
+            let auth_email = auth_email.clone();
+            return Box::pin(async move { Ok(auth_email) });
+        }
+
+        let id_token = id_token(req.headers(), req.path(), req.query_string());
+        let pool = req
+            .app_data::<web::Data<DbPool>>()
+            .map(|pool| pool.get_ref().clone());
+        let method = req.method().clone();
+
+        Box::pin(async move {
+            match id_token {
+                Some(id_token) => match identify(pool.as_ref(), &id_token).await {
+                    Some(identity) if identity.may(&method) => Ok(AuthEmail(identity.email)),
+                    Some(_) => Err(ErrorForbidden(READ_ONLY)),
+                    None => Err(ErrorUnauthorized("Unauthorized!")),
+                },
+                None => Err(ErrorBadRequest("No auth header present!")),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_google_token_info() {
+        let token_info = |aud: &str, email_verified: Value| TokenInfo {
+            email: String::from("a@x.com"),
+            aud: aud.to_string(),
+            email_verified,
+        };
+
+        assert_eq!(
+            token_info("ours", Value::from("true")).email_for("ours"),
+            Some(String::from("a@x.com"))
+        );
+        assert!(token_info("ours", Value::Bool(true))
+            .email_for("ours")
+            .is_some());
+        assert!(token_info("theirs", Value::from("true"))
+            .email_for("ours")
+            .is_none());
+        assert!(token_info("ours", Value::from("false"))
+            .email_for("ours")
+            .is_none());
+        assert!(token_info("ours", Value::Null).email_for("ours").is_none());
+    }
+}
@@ -0,0 +1,204 @@
+use diesel::prelude::*;
+use tokio_diesel::*;
+
+use crate::cost::CostContext;
+use crate::model::{Budget, BudgetAlert, Email};
+use crate::{household, webhook, DbPool};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BudgetStatus {
+    pub budget: Budget,
+    pub currencie_id: Option<i32>,
+    pub spent: f32,
+    pub remaining: f32,
+    pub is_over: bool,
+}
+
+pub fn budgets(conn: &SqliteConnection, email_id: i32) -> QueryResult<Vec<Budget>> {
+    use crate::schema::budgets::dsl;
+
+    dsl::budgets
+        .filter(dsl::email_id.eq(email_id))
+        .load::<Budget>(conn)
+}
+
+// Sets the email's budget for the category (or overall), replacing the limit if one exists
+pub fn save_budget(conn: &SqliteConnection, budget: &Budget) -> QueryResult<Budget> {
+    use crate::schema::budgets::dsl;
+
+    conn.transaction(|| {
+        let existing = budgets(conn, budget.email_id)?
+            .into_iter()
+            .find(|existing| existing.category_id == budget.category_id);
+
+        match existing {
+            Some(existing) => {
+                diesel::update(&existing)
+                    .set(dsl::monthly_limit.eq(budget.monthly_limit))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(dsl::budgets)
+                    .values(&Budget {
+                        id: None,
+                        created_at: None,
+                        updated_at: None,
+                        is_over: false,
+                        ..budget.clone()
+                    })
+                    .execute(conn)?;
+            }
+        }
+
+        budgets(conn, budget.email_id)?
+            .into_iter()
+            .find(|saved| saved.category_id == budget.category_id)
+            .ok_or(diesel::result::Error::NotFound)
+    })
+}
+
+pub fn delete_budget(conn: &SqliteConnection, budget_id: i32) -> QueryResult<usize> {
+    {
+        use crate::schema::budget_alerts::dsl;
+        diesel::delete(dsl::budget_alerts.filter(dsl::budget_id.eq(budget_id))).execute(conn)?;
+    }
+
+    use crate::schema::budgets::dsl::budgets;
+    diesel::delete(budgets.find(budget_id)).execute(conn)
+}
+
+// Current monthly spending against each of the email's budgets
+pub fn check(conn: &SqliteConnection, email: Email) -> QueryResult<Vec<BudgetStatus>> {
+    let email_budgets = match email.id {
+        Some(email_id) => budgets(conn, email_id)?,
+        None => vec![],
+    };
+    let context = CostContext::load(conn, email)?;
+
+    Ok(email_budgets
+        .into_iter()
+        .map(|budget| {
+            let spent: f32 = context
+                .active_subscriptions()
+                .filter(|subscription| {
+                    budget.category_id.is_none() || subscription.category_id == budget.category_id
+                })
+                .map(|subscription| context.monthly_cost(subscription))
+                .sum();
+
+            BudgetStatus {
+                currencie_id: context.email.currencie_id,
+                spent,
+                remaining: budget.monthly_limit - spent,
+                is_over: spent > budget.monthly_limit,
+                budget,
+            }
+        })
+        .collect())
+}
+
+// Records an alert for every budget whose spending crossed its limit, either way, since the
+// last run. `source` says what triggered the run, e.g. "subscription" or "rates".
+pub fn run_hooks(
+    conn: &SqliteConnection,
+    email_ids: Vec<i32>,
+    source: &str,
+) -> QueryResult<Vec<BudgetAlert>> {
+    let emails_list = {
+        use crate::schema::emails::dsl::*;
+        emails
+            .filter(id.eq_any(email_ids.into_iter().map(Some)))
+            .load::<Email>(conn)?
+    };
+
+    let mut alerts = vec![];
+
+    for email in emails_list {
+        for status in check(conn, email)? {
+            if status.is_over == status.budget.is_over {
+                continue;
+            }
+
+            {
+                use crate::schema::budgets::dsl::is_over;
+                diesel::update(&status.budget)
+                    .set(is_over.eq(status.is_over))
+                    .execute(conn)?;
+            }
+
+            let alert = BudgetAlert {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                budget_id: status.budget.id.unwrap_or(0),
+                email_id: status.budget.email_id,
+                monthly_limit: status.budget.monthly_limit,
+                spent: status.spent,
+                is_over: status.is_over,
+                source: source.to_string(),
+            };
+
+            use crate::schema::budget_alerts::dsl::budget_alerts;
+            diesel::insert_into(budget_alerts)
+                .values(&alert)
+                .execute(conn)?;
+
+            webhook::emit(
+                conn,
+                vec![alert.email_id],
+                webhook::BUDGET_CROSSED,
+                None,
+                serde_json::to_value(&alert).unwrap_or_default(),
+            )?;
+
+            println!(
+                "Budget {:?} of email {} is {} ({} of {}), from {}",
+                status.budget.id,
+                alert.email_id,
+                if alert.is_over { "over" } else { "back under" },
+                alert.spent,
+                alert.monthly_limit,
+                source
+            );
+
+            alerts.push(alert);
+        }
+    }
+
+    Ok(alerts)
+}
+
+// Runs the hooks for these emails and everyone sharing a household with them, whose totals
+// a change can also move. Failures are logged, they never fail the change itself.
+pub fn after_change(conn: &SqliteConnection, email_ids: Vec<i32>, source: &str) {
+    let res = email_ids
+        .into_iter()
+        .map(|email_id| household::related_email_ids(conn, email_id))
+        .collect::<QueryResult<Vec<Vec<i32>>>>()
+        .and_then(|related| {
+            let mut email_ids: Vec<i32> = related.into_iter().flatten().collect();
+            email_ids.sort_unstable();
+            email_ids.dedup();
+
+            run_hooks(conn, email_ids, source)
+        });
+
+    if let Err(e) = res {
+        println!("Error running budget hooks: {:?}", e);
+    }
+}
+
+// Rates move every converted total, so all budgets are checked
+pub fn run_all_hooks(conn: &SqliteConnection, source: &str) -> QueryResult<Vec<BudgetAlert>> {
+    use crate::schema::budgets::dsl::*;
+    let email_ids = budgets.select(email_id).distinct().load::<i32>(conn)?;
+
+    run_hooks(conn, email_ids, source)
+}
+
+pub async fn rates_changed(pool: &DbPool) {
+    match pool.run(|conn| run_all_hooks(conn, "rates")).await {
+        Ok(alerts) => println!("Budget alerts after rate update: {}", alerts.len()),
+        Err(e) => println!("Error running budget hooks: {:?}", e),
+    }
+}
@@ -0,0 +1,477 @@
+use diesel::{connection::SimpleConnection, prelude::*};
+use diesel_migrations::MigrationConnection;
+use std::{io, process};
+
+use crate::config::Config;
+use crate::model::{Email, Subscription};
+use crate::{
+    account, audit, budget, cost, currency, establish_pool, household, rates, run_migrations,
+    serve, webhook,
+};
+
+const USAGE: &str = "Usage: monty [command]
+
+Commands:
+    serve                     Run the HTTP server and rate poller (default)
+    migrate [--revert]        Run pending migrations, or revert the latest one
+    rates refresh             Fetch the latest rates from Fixer now, retrying failures
+    rates set <code> <rate>   Pin a currency rate so refreshes keep it, e.g. rates set EUR 1.0
+    rates unpin <code>        Go back to the provider's rate
+    rates custom <code> <rate>
+                              Add a currency the provider doesn't have, e.g. rates custom POINTS 100
+    users list                List all emails with their subscription count
+    user delete <email>       Delete an email and its subscriptions
+    user password <username>  Create a local account or change its password, read from stdin
+    export <email>            Print an email and its subscriptions as JSON
+";
+
+pub async fn run(config: Config, args: Vec<String>) {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    match args.as_slice() {
+        [] | ["serve"] => serve(config).await,
+        ["migrate"] => migrate(&config, false),
+        ["migrate", "--revert"] => migrate(&config, true),
+        ["rates", "refresh"] => rates_refresh(&config).await,
+        ["rates", "set", code, rate] => rates_set(&config, code, Some(rate)),
+        ["rates", "unpin", code] => rates_set(&config, code, None),
+        ["rates", "custom", code, rate] => rates_custom(&config, code, rate),
+        ["users", "list"] => users_list(&config),
+        ["user", "delete", email_name] => user_delete(&config, email_name),
+        ["user", "password", username] => user_password(&config, username),
+        ["export", email_name] => export(&config, email_name),
+        ["help"] | ["--help"] | ["-h"] => print!("{}", USAGE),
+        _ => {
+            eprint!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn migrate(config: &Config, revert: bool) {
+    let pool = establish_pool(&config.database_url);
+
+    if revert {
+        let conn = pool
+            .get()
+            .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+        match revert_latest(&conn) {
+            Ok(version) => println!("Reverted migration {}", version),
+            Err(e) => fail(format!("Error reverting migration! {}", e)),
+        }
+    } else {
+        run_migrations(&pool);
+        println!("Migrations up to date.");
+    }
+}
+
+// (version, down.sql) of every migration, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/down_migrations.rs"));
+
+// Runs the latest migration's down.sql, embedded like the migrations themselves so it works
+// outside the checkout. Like running them, this rebuilds tables, which needs foreign keys off.
+fn revert_latest(conn: &SqliteConnection) -> Result<String, String> {
+    let latest = conn
+        .latest_run_migration_version()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| String::from("No migrations have run"))?;
+    let down_sql = DOWN_MIGRATIONS
+        .iter()
+        .find(|(version, _)| *version == latest)
+        .map(|(_, down_sql)| *down_sql)
+        .ok_or_else(|| format!("Migration {} isn't known to this build", latest))?;
+
+    conn.batch_execute("PRAGMA foreign_keys = OFF;")
+        .map_err(|e| e.to_string())?;
+    let reverted = conn.transaction::<_, diesel::result::Error, _>(|| {
+        conn.batch_execute(down_sql)?;
+        diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = ?")
+            .bind::<diesel::sql_types::Text, _>(&latest)
+            .execute(conn)
+    });
+    conn.batch_execute("PRAGMA foreign_keys = ON;")
+        .map_err(|e| e.to_string())?;
+
+    reverted.map(|_| latest).map_err(|e| e.to_string())
+}
+
+async fn rates_refresh(config: &Config) {
+    let pool = establish_pool(&config.database_url);
+
+    run_migrations(&pool);
+
+    let outdated = rates::count_outdated_currencies(&pool).await;
+    println!("Currencies not updated: {}", outdated);
+
+    if let Err(e) = rates::refresh_rates(&pool, &config.fixer_api_key, &config.rate_polling).await {
+        fail(format!("Error refreshing rates! {}", e));
+    }
+}
+
+fn parse_rate(rate_str: &str) -> f32 {
+    match rate_str.parse::<f32>() {
+        Ok(new_rate) => new_rate,
+        _ => fail(format!("Invalid rate: {}", rate_str)),
+    }
+}
+
+fn rates_set(config: &Config, code: &str, rate_str: Option<&str>) {
+    let new_rate = rate_str.map(parse_rate);
+
+    let pool = establish_pool(&config.database_url);
+    run_migrations(&pool);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    match currency::pin_by_name(&conn, &audit::Actor::cli(), code, new_rate) {
+        Ok(saved) => {
+            match saved.manual_rate {
+                Some(pinned) => println!("Currency {} rate pinned to {}", saved.name, pinned),
+                None => println!("Currency {} rate set to {}", saved.name, saved.rate),
+            }
+
+            if let Err(e) = budget::run_all_hooks(&conn, "rates") {
+                println!("Error running budget hooks: {:?}", e);
+            }
+        }
+        Err(e) => fail(format!("Error setting currency {}! {}", code, e)),
+    }
+}
+
+fn rates_custom(config: &Config, code: &str, rate_str: &str) {
+    let body = currency::CustomCurrencyBody {
+        name: code.to_string(),
+        rate: parse_rate(rate_str),
+        display_name: None,
+        symbol: None,
+        minor_units: None,
+    };
+
+    let pool = establish_pool(&config.database_url);
+    run_migrations(&pool);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    match currency::create_custom(&conn, &audit::Actor::cli(), &body) {
+        Ok(created) => println!("Currency {} added at {}", created.name, created.rate),
+        Err(e) => fail(format!("Error adding currency {}! {}", code, e)),
+    }
+}
+
+fn users_list(config: &Config) {
+    let pool = establish_pool(&config.database_url);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    let emails_list = {
+        use crate::schema::emails::dsl::*;
+        emails
+            .load::<Email>(&conn)
+            .unwrap_or_else(|e| fail(format!("Error getting emails! {}", e)))
+    };
+
+    emails_list.iter().for_each(|email| {
+        use crate::schema::subscriptions::dsl::*;
+
+        let subscriptions_count = subscriptions
+            .filter(email_id.nullable().eq(email.id))
+            .load::<Subscription>(&conn)
+            .map(|subscriptions_list| subscriptions_list.len())
+            .unwrap_or(0);
+
+        println!(
+            "{}\t{}\t{} subscription(s)",
+            email.id.unwrap_or(0),
+            email.email,
+            subscriptions_count
+        );
+    });
+}
+
+fn user_delete(config: &Config, email_name: &str) {
+    let pool = establish_pool(&config.database_url);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    match delete_user(&conn, email_name) {
+        Ok(deleted_subscriptions) => println!(
+            "Deleted {} and {} subscription(s)",
+            email_name, deleted_subscriptions
+        ),
+        Err(diesel::result::Error::NotFound) => fail(format!("Email {} not found", email_name)),
+        Err(e) => fail(format!("Error deleting {}! {}", email_name, e)),
+    }
+}
+
+// Deletes the email with everything it owns, returns how many subscriptions went with it
+fn delete_user(conn: &SqliteConnection, email_name: &str) -> QueryResult<usize> {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        use crate::schema::emails::dsl::{email, emails};
+
+        let email_found = emails.filter(email.eq(email_name)).first::<Email>(conn)?;
+
+        let actor = audit::Actor::cli();
+
+        let subscriptions_found = {
+            use crate::schema::subscriptions::dsl::*;
+            subscriptions
+                .filter(email_id.nullable().eq(email_found.id))
+                .load::<Subscription>(conn)?
+        };
+        for subscription in subscriptions_found.iter() {
+            Subscription::delete(conn, subscription.id.unwrap_or(0))?;
+            audit::record(
+                conn,
+                &actor,
+                audit::SUBSCRIPTION,
+                email_found.id,
+                Some(subscription),
+                None,
+            )?;
+        }
+
+        {
+            use crate::schema::webhooks::dsl::*;
+            let webhook_ids = webhooks
+                .filter(email_id.nullable().eq(email_found.id))
+                .select(id)
+                .load::<Option<i32>>(conn)?;
+            for webhook_id in webhook_ids.iter().flatten() {
+                webhook::delete_webhook(conn, *webhook_id)?;
+            }
+        }
+        for found_budget in budget::budgets(conn, email_found.id.unwrap_or(0))? {
+            budget::delete_budget(conn, found_budget.id.unwrap_or(0))?;
+            audit::record(
+                conn,
+                &actor,
+                audit::BUDGET,
+                email_found.id,
+                Some(&found_budget),
+                None,
+            )?;
+        }
+        {
+            use crate::schema::budget_alerts::dsl::*;
+            diesel::delete(budget_alerts.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::categories::dsl::*;
+            diesel::delete(categories.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::api_keys::dsl::*;
+            diesel::delete(api_keys.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::rate_overrides::dsl::*;
+            diesel::delete(rate_overrides.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::local_accounts::dsl::*;
+            diesel::delete(local_accounts.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::household_invites::dsl::*;
+            diesel::delete(household_invites.filter(invited_by.eq(email_found.id)))
+                .execute(conn)?;
+        }
+
+        if let Some(email_found_id) = email_found.id {
+            for household_id in household::household_ids(conn, email_found_id)? {
+                household::remove_member(conn, household_id, email_found_id)?;
+            }
+        }
+        {
+            use crate::schema::spending_snapshots::dsl::*;
+            diesel::delete(spending_snapshots.filter(email_id.nullable().eq(email_found.id)))
+                .execute(conn)?;
+        }
+
+        {
+            use crate::schema::sessions;
+            diesel::delete(sessions::table.filter(sessions::email.eq(&email_found.email)))
+                .execute(conn)?;
+        }
+
+        diesel::delete(&email_found).execute(conn)?;
+        audit::record(
+            conn,
+            &actor,
+            audit::EMAIL,
+            email_found.id,
+            Some(&email_found),
+            None,
+        )?;
+
+        Ok(subscriptions_found.len())
+    })
+}
+
+fn user_password(config: &Config, username: &str) {
+    if !config.local_accounts {
+        println!(
+            "Note: local_accounts is off in env.json, the account can't log in until it's on."
+        );
+    }
+
+    eprintln!("Password for {}:", username);
+    let mut password = String::new();
+    if let Err(e) = io::stdin().read_line(&mut password) {
+        fail(format!("Error reading password! {}", e));
+    }
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+
+    let pool = establish_pool(&config.database_url);
+    run_migrations(&pool);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    match account::set_password(&conn, username, password) {
+        Ok(_) => println!("Password set for {}", username),
+        Err(e) => fail(format!("Error setting password for {}! {}", username, e)),
+    }
+}
+
+fn export(config: &Config, email_name: &str) {
+    let pool = establish_pool(&config.database_url);
+    let conn = pool
+        .get()
+        .unwrap_or_else(|e| fail(format!("Getting connection error! {}", e)));
+
+    let res = {
+        use crate::schema::emails::dsl::{email, emails};
+        use crate::schema::subscriptions::dsl::{email_id, subscriptions};
+
+        emails
+            .filter(email.eq(email_name))
+            .first::<Email>(&conn)
+            .and_then(|email_found| {
+                let subscriptions_list = subscriptions
+                    .filter(email_id.nullable().eq(email_found.id))
+                    .load::<Subscription>(&conn)?;
+                let context = cost::CostContext::load(&conn, email_found.clone())?;
+                let total = context.total().formatted(cost::find_currency(
+                    &context.currencies,
+                    email_found.currencie_id,
+                ));
+
+                // Each subscription's cost in its own currency
+                let costs: Vec<serde_json::Value> = subscriptions_list
+                    .iter()
+                    .map(|subscription| {
+                        serde_json::json!({
+                            "subscription_id": subscription.id,
+                            "cost": currency::format_amount(
+                                subscription.cost as f64,
+                                cost::find_currency(&context.currencies, subscription.currencie_id),
+                            ),
+                        })
+                    })
+                    .collect();
+
+                Ok((email_found, subscriptions_list, total, costs))
+            })
+    };
+
+    match res {
+        Ok((email_found, subscriptions_list, total, costs)) => {
+            let export_json = serde_json::json!({
+                "email": email_found,
+                "subscriptions": subscriptions_list,
+                "costs": costs,
+                "total": total,
+            });
+
+            match serde_json::to_string_pretty(&export_json) {
+                Ok(export_str) => println!("{}", export_str),
+                Err(e) => fail(format!("Error serializing export! {}", e)),
+            }
+        }
+        Err(diesel::result::Error::NotFound) => fail(format!("Email {} not found", email_name)),
+        Err(e) => fail(format!("Error exporting {}! {}", email_name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverts_and_reruns_migrations() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+
+        {
+            let conn = pool.get().unwrap();
+            diesel::sql_query("insert into emails (email) values ('a@x.com')")
+                .execute(&conn)
+                .unwrap();
+
+            // Back to the tables from before the constraints
+            while revert_latest(&conn).unwrap() != "20261019090000" {}
+        }
+
+        run_migrations(&pool);
+
+        let conn = pool.get().unwrap();
+        let emails_list = {
+            use crate::schema::emails::dsl::*;
+            emails.load::<Email>(&conn).unwrap()
+        };
+        assert_eq!(emails_list.len(), 1);
+        assert_eq!(emails_list[0].email, "a@x.com");
+    }
+
+    #[test]
+    fn embeds_every_down_migration() {
+        // The ones from before constraints were added have nothing to undo
+        for (version, down_sql) in DOWN_MIGRATIONS
+            .iter()
+            .filter(|(version, _)| *version >= "20261019090000")
+        {
+            assert!(
+                down_sql
+                    .lines()
+                    .any(|line| !line.trim().is_empty() && !line.starts_with("--")),
+                "{} has an empty down.sql",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn deletes_users_with_sent_invites() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+
+        let conn = pool.get().unwrap();
+        conn.batch_execute(
+            "insert into emails (email) values ('a@x.com');
+            insert into households (name) values ('Home');
+            insert into household_members (household_id, email_id, role) values (1, 1, 'owner');
+            insert into household_invites (household_id, email, role, invited_by)
+                values (1, 'b@x.com', 'viewer', 1);",
+        )
+        .unwrap();
+
+        assert_eq!(delete_user(&conn, "a@x.com").unwrap(), 0);
+        assert!(household::invites_for(&conn, "b@x.com").unwrap().is_empty());
+    }
+}
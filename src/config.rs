@@ -0,0 +1,121 @@
+use serde_json::Value;
+use std::{
+    env,
+    fs::File,
+    io::{BufReader, Read},
+};
+
+use crate::{oidc, ratelimit, rates};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub database_url: String,
+    pub fixer_api_key: String,
+    pub app_port: String,
+    pub snapshot_interval: String,
+    pub base_url: String,
+    // Routes anyone may call besides the built in ones, like "GET /path/{id}"
+    pub public_routes: Vec<String>,
+    pub admin_emails: Vec<String>,
+    // Google id tokens must be issued to this client, the bundled frontend's when not set
+    pub google_client_id: String,
+    // Sign in providers besides Google
+    pub oidc_providers: Vec<oidc::Provider>,
+    pub local_accounts: bool,
+    // Signs session access tokens, random per run when not set
+    pub session_secret: String,
+    pub access_token_minutes: i64,
+    pub refresh_token_days: i64,
+    pub rate_limits: ratelimit::Settings,
+    pub rate_polling: rates::Settings,
+}
+
+fn strings(val: &Value) -> Vec<String> {
+    val.as_array()
+        .map(|vals| {
+            vals.iter()
+                .filter_map(|val| val.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Reads env.json and .env into a Config
+pub fn load() -> Config {
+    let mut config = Config::default();
+
+    // Parse env.json
+    match File::open("env.json") {
+        Ok(contents) => {
+            let mut json_str = String::new();
+
+            if BufReader::new(contents)
+                .read_to_string(&mut json_str)
+                .is_err()
+            {
+                println!("Error reading env.json!");
+            }
+
+            match serde_json::from_str(&json_str) as Result<Value, _> {
+                Ok(val) => {
+                    if let Some(fixer_api_key) = val["fixer_api_key"].as_str() {
+                        config.fixer_api_key = fixer_api_key.to_string();
+                    }
+                    if let Some(server_port) = val["server_port"].as_str() {
+                        config.app_port = server_port.to_string();
+                    }
+                    if let Some(snapshot_interval) = val["snapshot_interval"].as_str() {
+                        config.snapshot_interval = snapshot_interval.to_string();
+                    }
+                    if let Some(base_url) = val["base_url"].as_str() {
+                        config.base_url = base_url.to_string();
+                    }
+                    if let Some(google_client_id) = val["google_client_id"].as_str() {
+                        config.google_client_id = google_client_id.to_string();
+                    }
+                    config.public_routes = strings(&val["public_routes"]);
+                    config.admin_emails = strings(&val["admin_emails"]);
+                    match serde_json::from_value(val["oidc_providers"].clone()) {
+                        Ok(oidc_providers) => config.oidc_providers = oidc_providers,
+                        Err(_) if val["oidc_providers"].is_null() => {}
+                        Err(e) => println!("Error parsing oidc_providers! {}", e),
+                    }
+                    config.local_accounts = val["local_accounts"].as_bool().unwrap_or(false);
+                    if let Some(session_secret) = val["session_secret"].as_str() {
+                        config.session_secret = session_secret.to_string();
+                    }
+                    if let Some(access_token_minutes) = val["access_token_minutes"].as_i64() {
+                        config.access_token_minutes = access_token_minutes;
+                    }
+                    if let Some(refresh_token_days) = val["refresh_token_days"].as_i64() {
+                        config.refresh_token_days = refresh_token_days;
+                    }
+                    match serde_json::from_value(val["rate_limits"].clone()) {
+                        Ok(rate_limits) => config.rate_limits = rate_limits,
+                        Err(_) if val["rate_limits"].is_null() => {}
+                        Err(e) => println!("Error parsing rate_limits! {}", e),
+                    }
+                    match serde_json::from_value(val["rate_polling"].clone()) {
+                        Ok(rate_polling) => config.rate_polling = rate_polling,
+                        Err(_) if val["rate_polling"].is_null() => {}
+                        Err(e) => println!("Error parsing rate_polling! {}", e),
+                    }
+                }
+                _ => {
+                    println!("Error parsing env.json")
+                }
+            }
+        }
+        _ => {
+            println!("Error opening env.json!");
+        }
+    }
+
+    // Parse .env
+    if let Ok(database_url) = env::var("DATABASE_URL") {
+        config.database_url = database_url;
+    }
+
+    config
+}
@@ -0,0 +1,714 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::model::{
+    Category, Currencie, Email, HouseholdMember, Interval, Subscription, SubscriptionPrice,
+    SubscriptionShare, Tag,
+};
+use crate::{currency, household, lifecycle, price};
+
+// When the promotional price stops applying, counted from the end of the trial if any
+pub fn promo_ends_at(subscription: &Subscription) -> Option<NaiveDateTime> {
+    let promo_starts_at = subscription.trial_ends_at.or(subscription.created_at)?;
+
+    subscription
+        .promo_months
+        .map(|promo_months| add_months(promo_starts_at, promo_months))
+}
+
+// The price charged at a given time: nothing during the trial, then the promotional price,
+// then the regular cost
+pub fn effective_cost(subscription: &Subscription, regular_cost: f32, at: NaiveDateTime) -> f32 {
+    if subscription
+        .trial_ends_at
+        .map(|trial_ends_at| at < trial_ends_at)
+        .unwrap_or(false)
+    {
+        return 0.0;
+    }
+
+    match (subscription.promo_cost, promo_ends_at(subscription)) {
+        (Some(promo_cost), Some(promo_ends_at)) if at < promo_ends_at => promo_cost,
+        _ => regular_cost,
+    }
+}
+
+// Same formula as getSubscriptionMonthlyPrice in the frontend
+pub fn monthly_cost(
+    subscription: &Subscription,
+    intervals: &[Interval],
+    currencies: &[Currencie],
+    target_currencie_id: Option<i32>,
+    at: NaiveDateTime,
+) -> f32 {
+    let interval_modifier = intervals
+        .iter()
+        .find(|interval| interval.id.is_some() && interval.id == subscription.interval_id)
+        .map(|interval| interval.modifier)
+        .unwrap_or(1.0);
+
+    let interval_amount = subscription.interval_amount.max(1) as f32;
+
+    effective_cost(subscription, subscription.cost, at) / interval_amount
+        * interval_modifier
+        * conversion_rate(currencies, subscription.currencie_id, target_currencie_id)
+}
+
+pub fn conversion_rate(currencies: &[Currencie], from: Option<i32>, to: Option<i32>) -> f32 {
+    if from.is_none() || to.is_none() || from == to {
+        return 1.0;
+    }
+
+    let find_rate = |currencie_id: Option<i32>| {
+        currencies
+            .iter()
+            .find(|currency| currency.id == currencie_id)
+            .map(|currency| currency.rate)
+            .unwrap_or(0.0)
+    };
+
+    let from_rate = find_rate(from);
+
+    if from_rate > 0.0 {
+        find_rate(to) / from_rate
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CostTotal {
+    pub monthly: f32,
+    pub annual: f32,
+    // Set on totals sent to clients, so they don't format amounts themselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<FormattedTotal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormattedTotal {
+    pub monthly: String,
+    pub annual: String,
+}
+
+impl CostTotal {
+    pub fn add(&mut self, monthly: f32) {
+        self.monthly += monthly;
+        self.annual += monthly * 12.0;
+    }
+
+    pub fn formatted(mut self, currency: Option<&Currencie>) -> CostTotal {
+        self.formatted = Some(FormattedTotal {
+            monthly: currency::format_amount(self.monthly as f64, currency),
+            annual: currency::format_amount(self.annual as f64, currency),
+        });
+        self
+    }
+}
+
+pub fn find_currency(currencies: &[Currencie], currencie_id: Option<i32>) -> Option<&Currencie> {
+    currencies
+        .iter()
+        .find(|currency| currencie_id.is_some() && currency.id == currencie_id)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CategoryCost {
+    pub category_id: Option<i32>,
+    pub name: String,
+    pub total: CostTotal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCost {
+    pub name: String,
+    pub total: CostTotal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Breakdown {
+    pub currencie_id: Option<i32>,
+    pub total: CostTotal,
+    pub categories: Vec<CategoryCost>,
+    pub tags: Vec<TagCost>,
+}
+
+// Everything needed to price an email's subscriptions, including those shared with it
+// through a household
+pub struct CostContext {
+    pub email: Email,
+    pub subscriptions: Vec<Subscription>,
+    pub intervals: Vec<Interval>,
+    pub currencies: Vec<Currencie>,
+    pub members: Vec<HouseholdMember>,
+    pub shares: Vec<SubscriptionShare>,
+}
+
+impl CostContext {
+    pub fn load(conn: &SqliteConnection, email: Email) -> QueryResult<CostContext> {
+        let household_ids = match email.id {
+            Some(email_id) => household::household_ids(conn, email_id)?,
+            None => vec![],
+        };
+        let subscriptions_list = {
+            use crate::schema::subscriptions::dsl::*;
+            subscriptions
+                .filter(
+                    email_id
+                        .nullable()
+                        .eq(email.id)
+                        .or(household_id.eq_any(household_ids.clone())),
+                )
+                .load::<Subscription>(conn)?
+        };
+        let members_list = household::members(conn, household_ids)?;
+        let shares_list = household::shares(
+            conn,
+            subscriptions_list
+                .iter()
+                .filter_map(|subscription| subscription.id)
+                .collect(),
+        )?;
+        let intervals_list = {
+            use crate::schema::intervals::dsl::*;
+            intervals.load::<Interval>(conn)?
+        };
+        let currencies_list = {
+            use crate::schema::currencies::dsl::*;
+            currencies.load::<Currencie>(conn)?
+        };
+        let currencies_list = currency::with_overrides(conn, currencies_list, email.id)?;
+
+        Ok(CostContext {
+            email,
+            subscriptions: subscriptions_list,
+            intervals: intervals_list,
+            currencies: currencies_list,
+            members: members_list,
+            shares: shares_list,
+        })
+    }
+
+    // The part of the subscription this email carries
+    pub fn share(&self, subscription: &Subscription) -> f32 {
+        match self.email.id {
+            Some(email_id) => {
+                household::share_of(subscription, &self.members, &self.shares, email_id)
+            }
+            None => 1.0,
+        }
+    }
+
+    // This email's share of the subscription, per month in its currency
+    pub fn monthly_cost(&self, subscription: &Subscription) -> f32 {
+        monthly_cost(
+            subscription,
+            &self.intervals,
+            &self.currencies,
+            self.email.currencie_id,
+            Utc::now().naive_utc(),
+        ) * self.share(subscription)
+    }
+
+    // Subscriptions counted in totals right now
+    pub fn active_subscriptions(&self) -> impl Iterator<Item = &Subscription> {
+        let now = Utc::now().naive_utc();
+
+        self.subscriptions.iter().filter(move |subscription| {
+            lifecycle::is_active_at(subscription, now) && self.share(subscription) > 0.0
+        })
+    }
+
+    pub fn total(&self) -> CostTotal {
+        let mut total = CostTotal::default();
+
+        self.active_subscriptions()
+            .for_each(|subscription| total.add(self.monthly_cost(subscription)));
+
+        total
+    }
+}
+
+pub fn breakdown(conn: &SqliteConnection, email: Email) -> QueryResult<Breakdown> {
+    let context = CostContext::load(conn, email)?;
+
+    let categories_list = {
+        use crate::schema::categories::dsl::*;
+        categories
+            .filter(email_id.nullable().eq(context.email.id))
+            .load::<Category>(conn)?
+    };
+    let tags_list = {
+        use crate::schema::tags::dsl::*;
+        let subscription_ids: Vec<i32> = context
+            .subscriptions
+            .iter()
+            .filter_map(|subscription| subscription.id)
+            .collect();
+        tags.filter(subscription_id.eq_any(subscription_ids))
+            .load::<Tag>(conn)?
+    };
+
+    let mut category_costs: BTreeMap<Option<i32>, CostTotal> = BTreeMap::new();
+    let mut tag_costs: BTreeMap<String, CostTotal> = BTreeMap::new();
+
+    context.active_subscriptions().for_each(|subscription| {
+        let monthly = context.monthly_cost(subscription);
+
+        category_costs
+            .entry(subscription.category_id)
+            .or_default()
+            .add(monthly);
+
+        tags_list
+            .iter()
+            .filter(|tag| Some(tag.subscription_id) == subscription.id)
+            .for_each(|tag| tag_costs.entry(tag.name.clone()).or_default().add(monthly));
+    });
+
+    let shown_in = find_currency(&context.currencies, context.email.currencie_id);
+
+    Ok(Breakdown {
+        currencie_id: context.email.currencie_id,
+        total: context.total().formatted(shown_in),
+        categories: category_costs
+            .into_iter()
+            .map(|(category_id, total)| CategoryCost {
+                category_id,
+                name: categories_list
+                    .iter()
+                    .find(|category| category_id.is_some() && category.id == category_id)
+                    .map(|category| category.name.clone())
+                    .unwrap_or_else(|| String::from("Uncategorized")),
+                total: total.formatted(shown_in),
+            })
+            .collect(),
+        tags: tag_costs
+            .into_iter()
+            .map(|(name, total)| TagCost {
+                name,
+                total: total.formatted(shown_in),
+            })
+            .collect(),
+    })
+}
+
+// Upper bound on generated charges, so a daily subscription over decades can't run away
+const MAX_CHARGES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IntervalStep {
+    Days(i64),
+    Months(i32),
+}
+
+fn interval_step(intervals: &[Interval], interval_id: Option<i32>) -> IntervalStep {
+    let interval_name = intervals
+        .iter()
+        .find(|interval| interval.id.is_some() && interval.id == interval_id)
+        .map(|interval| interval.name.as_str());
+
+    match interval_name {
+        Some("Day") => IntervalStep::Days(1),
+        Some("Week") => IntervalStep::Days(7),
+        Some("Year") => IntervalStep::Months(12),
+        _ => IntervalStep::Months(1),
+    }
+}
+
+// Adds months, clamping to the end of shorter months (Jan 31 + 1 month = Feb 28)
+fn add_months(day: NaiveDateTime, months: i32) -> NaiveDateTime {
+    let total_months = day.year() * 12 + day.month0() as i32 + months;
+    let year = total_months.div_euclid(12);
+    let month = total_months.rem_euclid(12) as u32 + 1;
+
+    (1..=day.day())
+        .rev()
+        .find_map(|month_day| NaiveDate::from_ymd_opt(year, month, month_day))
+        .map(|date| date.and_time(day.time()))
+        .unwrap_or(day)
+}
+
+fn advance(anchor: NaiveDateTime, step: IntervalStep, times: i32) -> NaiveDateTime {
+    match step {
+        IntervalStep::Days(days) => anchor + Duration::days(days * times as i64),
+        IntervalStep::Months(months) => add_months(anchor, months * times),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Charge {
+    pub day: NaiveDateTime,
+    pub cost: f32,
+    pub currencie_id: Option<i32>,
+    pub converted_cost: f32,
+}
+
+// Charges from the subscription's first price (or trial end) onwards, each billed at the
+// price in effect on that day, returning those in [from, to)
+pub fn charges(
+    subscription: &Subscription,
+    prices: &[SubscriptionPrice],
+    intervals: &[Interval],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Vec<(NaiveDateTime, SubscriptionPrice)> {
+    let mut subscription_prices: Vec<SubscriptionPrice> = prices
+        .iter()
+        .filter(|subscription_price| Some(subscription_price.subscription_id) == subscription.id)
+        .cloned()
+        .collect();
+    subscription_prices.sort_by_key(|subscription_price| subscription_price.effective_from);
+
+    if subscription_prices.is_empty() {
+        match subscription.created_at {
+            Some(created_at) => subscription_prices.push(SubscriptionPrice {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                subscription_id: subscription.id.unwrap_or(0),
+                cost: subscription.cost,
+                currencie_id: subscription.currencie_id,
+                interval_id: subscription.interval_id,
+                interval_amount: subscription.interval_amount,
+                effective_from: created_at,
+                effective_to: None,
+            }),
+            None => return vec![],
+        }
+    }
+
+    let price_at = |day: NaiveDateTime| {
+        subscription_prices
+            .iter()
+            .rev()
+            .find(|subscription_price| subscription_price.effective_from <= day)
+            .unwrap_or(&subscription_prices[0])
+    };
+
+    let mut day = match subscription.trial_ends_at {
+        Some(trial_ends_at) if trial_ends_at > subscription_prices[0].effective_from => {
+            trial_ends_at
+        }
+        _ => subscription_prices[0].effective_from,
+    };
+    let mut anchor = day;
+    let mut anchor_step = None;
+    let mut times = 0;
+    let mut found = vec![];
+
+    for _ in 0..MAX_CHARGES {
+        if day >= to {
+            break;
+        }
+
+        let current_price = price_at(day);
+
+        if day >= from && lifecycle::is_active_at(subscription, day) {
+            found.push((
+                day,
+                SubscriptionPrice {
+                    cost: effective_cost(subscription, current_price.cost, day),
+                    ..current_price.clone()
+                },
+            ));
+        }
+
+        // Restart counting from here when the billing interval changes
+        let step = (
+            interval_step(intervals, current_price.interval_id),
+            current_price.interval_amount.max(1),
+        );
+        if anchor_step != Some(step) {
+            anchor = day;
+            anchor_step = Some(step);
+            times = 0;
+        }
+
+        times += 1;
+        day = advance(anchor, step.0, step.1 * times);
+    }
+
+    found
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaidSubscription {
+    pub subscription_id: Option<i32>,
+    pub name: String,
+    pub total: f32,
+    pub charges: Vec<Charge>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaidReport {
+    pub currencie_id: Option<i32>,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub total: f32,
+    pub subscriptions: Vec<PaidSubscription>,
+}
+
+// What an email actually paid between from and to, converted at current rates
+pub fn paid(
+    conn: &SqliteConnection,
+    email: Email,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> QueryResult<PaidReport> {
+    let context = CostContext::load(conn, email)?;
+    let prices = price::subscription_prices(
+        conn,
+        context
+            .subscriptions
+            .iter()
+            .filter_map(|subscription| subscription.id)
+            .collect(),
+    )?;
+
+    let paid_subscriptions: Vec<PaidSubscription> = context
+        .subscriptions
+        .iter()
+        .filter(|subscription| context.share(subscription) > 0.0)
+        .map(|subscription| {
+            let share = context.share(subscription);
+            let subscription_charges: Vec<Charge> =
+                charges(subscription, &prices, &context.intervals, from, to)
+                    .into_iter()
+                    .map(|(day, charged_price)| Charge {
+                        day,
+                        cost: charged_price.cost * share,
+                        currencie_id: charged_price.currencie_id,
+                        converted_cost: charged_price.cost
+                            * share
+                            * conversion_rate(
+                                &context.currencies,
+                                charged_price.currencie_id,
+                                context.email.currencie_id,
+                            ),
+                    })
+                    .collect();
+
+            PaidSubscription {
+                subscription_id: subscription.id,
+                name: subscription.name.clone(),
+                total: subscription_charges
+                    .iter()
+                    .map(|charge| charge.converted_cost)
+                    .sum(),
+                charges: subscription_charges,
+            }
+        })
+        .collect();
+
+    Ok(PaidReport {
+        currencie_id: context.email.currencie_id,
+        from,
+        to,
+        total: paid_subscriptions
+            .iter()
+            .map(|paid_subscription| paid_subscription.total)
+            .sum(),
+        subscriptions: paid_subscriptions,
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpcomingCharge {
+    pub subscription_id: Option<i32>,
+    pub name: String,
+    pub charge: Charge,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrialEnding {
+    pub subscription_id: Option<i32>,
+    pub name: String,
+    pub trial_ends_at: NaiveDateTime,
+    pub first_charge: Option<Charge>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Upcoming {
+    pub currencie_id: Option<i32>,
+    pub charges: Vec<UpcomingCharge>,
+    pub trials_ending: Vec<TrialEnding>,
+}
+
+// Charges due in the next `days` days, and trials converting to paid in that window
+pub fn upcoming(conn: &SqliteConnection, email: Email, days: i64) -> QueryResult<Upcoming> {
+    let context = CostContext::load(conn, email)?;
+    let prices = price::subscription_prices(
+        conn,
+        context
+            .subscriptions
+            .iter()
+            .filter_map(|subscription| subscription.id)
+            .collect(),
+    )?;
+
+    let from = Utc::now().naive_utc();
+    let to = from + Duration::days(days);
+
+    let to_charge = |subscription: &Subscription,
+                     day: NaiveDateTime,
+                     charged_price: SubscriptionPrice| Charge {
+        day,
+        cost: charged_price.cost * context.share(subscription),
+        currencie_id: charged_price.currencie_id,
+        converted_cost: charged_price.cost
+            * context.share(subscription)
+            * conversion_rate(
+                &context.currencies,
+                charged_price.currencie_id,
+                context.email.currencie_id,
+            ),
+    };
+
+    let mut upcoming_charges: Vec<UpcomingCharge> = context
+        .subscriptions
+        .iter()
+        .filter(|subscription| context.share(subscription) > 0.0)
+        .flat_map(|subscription| {
+            charges(subscription, &prices, &context.intervals, from, to)
+                .into_iter()
+                .map(move |(day, charged_price)| (subscription, day, charged_price))
+        })
+        .map(|(subscription, day, charged_price)| UpcomingCharge {
+            subscription_id: subscription.id,
+            name: subscription.name.clone(),
+            charge: to_charge(subscription, day, charged_price),
+        })
+        .collect();
+    upcoming_charges.sort_by_key(|upcoming_charge| upcoming_charge.charge.day);
+
+    let trials_ending = context
+        .active_subscriptions()
+        .filter_map(|subscription| {
+            let trial_ends_at = subscription.trial_ends_at?;
+
+            if trial_ends_at < from || trial_ends_at >= to {
+                return None;
+            }
+
+            Some(TrialEnding {
+                subscription_id: subscription.id,
+                name: subscription.name.clone(),
+                trial_ends_at,
+                first_charge: charges(
+                    subscription,
+                    &prices,
+                    &context.intervals,
+                    trial_ends_at,
+                    trial_ends_at + Duration::seconds(1),
+                )
+                .into_iter()
+                .next()
+                .map(|(day, charged_price)| to_charge(subscription, day, charged_price)),
+            })
+        })
+        .collect();
+
+    Ok(Upcoming {
+        currencie_id: context.email.currencie_id,
+        charges: upcoming_charges,
+        trials_ending,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_subscription;
+
+    fn day(year: i32, month: u32, month_day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, month_day).and_hms(0, 0, 0)
+    }
+
+    fn intervals() -> Vec<Interval> {
+        vec![Interval {
+            id: Some(1),
+            created_at: None,
+            updated_at: None,
+            name: String::from("Day"),
+            modifier: 30.0,
+        }]
+    }
+
+    fn charged(
+        subscription: &Subscription,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<(NaiveDateTime, f32)> {
+        charges(subscription, &[], &intervals(), from, to)
+            .into_iter()
+            .map(|(charge_day, charge_price)| (charge_day, charge_price.cost))
+            .collect()
+    }
+
+    #[test]
+    fn clamps_to_the_end_of_the_month() {
+        assert_eq!(add_months(day(2026, 1, 31), 1), day(2026, 2, 28));
+        assert_eq!(add_months(day(2024, 1, 31), 1), day(2024, 2, 29));
+        assert_eq!(add_months(day(2026, 3, 31), -1), day(2026, 2, 28));
+        assert_eq!(add_months(day(2026, 12, 15), 1), day(2027, 1, 15));
+        assert_eq!(add_months(day(2026, 1, 15), -13), day(2024, 12, 15));
+    }
+
+    #[test]
+    fn charges_from_the_first_day() {
+        let mut subscription = test_subscription(1, 1, 10.0);
+        subscription.created_at = Some(day(2026, 1, 31));
+
+        // Counted from the first charge, so a short month doesn't move the later ones
+        assert_eq!(
+            charged(&subscription, day(2026, 1, 1), day(2026, 5, 1)),
+            vec![
+                (day(2026, 1, 31), 10.0),
+                (day(2026, 2, 28), 10.0),
+                (day(2026, 3, 31), 10.0),
+                (day(2026, 4, 30), 10.0),
+            ]
+        );
+        // Only the ones in range
+        assert_eq!(
+            charged(&subscription, day(2026, 3, 1), day(2026, 4, 1)),
+            vec![(day(2026, 3, 31), 10.0)]
+        );
+    }
+
+    #[test]
+    fn goes_from_trial_to_promo_to_regular() {
+        let mut subscription = test_subscription(1, 1, 10.0);
+        subscription.trial_ends_at = Some(day(2026, 2, 1));
+        subscription.promo_cost = Some(5.0);
+        subscription.promo_months = Some(2);
+
+        assert_eq!(promo_ends_at(&subscription), Some(day(2026, 4, 1)));
+        assert_eq!(effective_cost(&subscription, 10.0, day(2026, 1, 15)), 0.0);
+        assert_eq!(effective_cost(&subscription, 10.0, day(2026, 2, 1)), 5.0);
+        assert_eq!(effective_cost(&subscription, 10.0, day(2026, 4, 1)), 10.0);
+
+        // Nothing is charged during the trial
+        assert_eq!(
+            charged(&subscription, day(2026, 1, 1), day(2026, 6, 1)),
+            vec![
+                (day(2026, 2, 1), 5.0),
+                (day(2026, 3, 1), 5.0),
+                (day(2026, 4, 1), 10.0),
+                (day(2026, 5, 1), 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_max_charges() {
+        let mut subscription = test_subscription(1, 1, 1.0);
+        subscription.interval_id = Some(1);
+
+        let daily = charged(&subscription, day(2026, 1, 1), day(2126, 1, 1));
+        assert_eq!(daily.len(), MAX_CHARGES);
+        assert_eq!(daily[1].0, day(2026, 1, 2));
+    }
+}
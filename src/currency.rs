@@ -0,0 +1,641 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::audit::{self, Actor};
+use crate::model::{Currencie, RateOverride};
+
+// Admins can pin a currency's rate over the provider's and add custom currencies the provider
+// doesn't know, like loyalty points. Every email can also set its own rates, which are only
+// used for its own costs. The currencies table is the list of supported codes: seeded by a
+// migration and kept up to date from the provider's rates.
+
+// Longest custom currency code
+const MAX_NAME: usize = 12;
+// Most digits after the decimal point, enough for BTC
+const MAX_MINOR_UNITS: i32 = 8;
+// Seeded inactive as withdrawn from ISO 4217, providers may still send their last rates
+const WITHDRAWN: [&str; 12] = [
+    "ANG", "BGN", "BYR", "CUC", "HRK", "LTL", "LVL", "MRO", "STD", "VEF", "ZMK", "ZWL",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct CustomCurrencyBody {
+    pub name: String,
+    pub rate: f32,
+    pub display_name: Option<String>,
+    pub symbol: Option<String>,
+    // 2 when not set
+    pub minor_units: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinBody {
+    // Null goes back to the provider's rate
+    pub rate: Option<f32>,
+}
+
+fn check_rate(rate: f32) -> Result<(), String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("rate must be a positive number"))
+    }
+}
+
+fn find_by_name(conn: &SqliteConnection, code: &str) -> QueryResult<Option<Currencie>> {
+    use crate::schema::currencies::dsl::*;
+
+    currencies
+        .filter(name.eq(code))
+        .first::<Currencie>(conn)
+        .optional()
+}
+
+pub fn create_custom(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    body: &CustomCurrencyBody,
+) -> Result<Currencie, String> {
+    use crate::schema::currencies::dsl::*;
+
+    let code = body.name.trim().to_uppercase();
+
+    if code.is_empty()
+        || code.len() > MAX_NAME
+        || !code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!(
+            "name must be 1 to {} letters, digits, _ or -",
+            MAX_NAME
+        ));
+    }
+    check_rate(body.rate)?;
+
+    let digits = body.minor_units.unwrap_or(2);
+    if !(0..=MAX_MINOR_UNITS).contains(&digits) {
+        return Err(format!("minor_units must be 0 to {}", MAX_MINOR_UNITS));
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        if find_by_name(conn, &code)?.is_some() {
+            return Ok(None);
+        }
+
+        diesel::insert_into(currencies)
+            .values(Currencie {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                name: code.clone(),
+                rate: body.rate,
+                last_update_day: None,
+                manual_rate: None,
+                provider_rate: None,
+                custom: true,
+                display_name: body.display_name.clone(),
+                symbol: body.symbol.clone(),
+                minor_units: digits,
+                active: true,
+            })
+            .execute(conn)?;
+
+        let created = currencies.filter(name.eq(&code)).first::<Currencie>(conn)?;
+        audit::record(conn, actor, audit::RATE, None, None, Some(&created))?;
+
+        Ok(Some(created))
+    })
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Currency {} already exists", code))
+}
+
+// What a rate refresh changed
+#[derive(Debug, Default)]
+pub struct ProviderUpdate {
+    pub updated: usize,
+    // Codes the provider reported for the first time
+    pub added: Vec<String>,
+    // Codes the provider stopped reporting, now inactive
+    pub retired: Vec<String>,
+    // Codes sent with a zero, negative or missing rate, their last rate is kept
+    pub rejected: Vec<String>,
+}
+
+fn is_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_NAME
+        && code
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+// Stores the provider's rates by code, as of day, other than ones that aren't positive. New
+// codes are added, and codes it no longer reports are marked inactive with their last rate kept,
+// withdrawn ones stay inactive. Pinned rates stay, custom currencies are left alone.
+pub fn apply_provider_rates(
+    conn: &SqliteConnection,
+    rates: &BTreeMap<String, f64>,
+    day: NaiveDateTime,
+) -> QueryResult<ProviderUpdate> {
+    use crate::schema::currencies::dsl::*;
+
+    // Nothing to go by, rather than retiring every code
+    if rates.is_empty() {
+        return Ok(ProviderUpdate::default());
+    }
+
+    conn.transaction(|| {
+        let mut update = ProviderUpdate::default();
+        let known = currencies.load::<Currencie>(conn)?;
+
+        for (code, new_rate) in rates.iter() {
+            let new_rate = *new_rate as f32;
+            if check_rate(new_rate).is_err() {
+                update.rejected.push(code.clone());
+                continue;
+            }
+
+            match known.iter().find(|currency| &currency.name == code) {
+                Some(currency) if currency.custom => {
+                    println!("Provider has {}, keeping the custom currency", code);
+                }
+                Some(currency) => {
+                    // Provided again after being retired
+                    let is_active = currency.active || !WITHDRAWN.contains(&code.as_str());
+
+                    diesel::update(currencies.find(currency.id))
+                        .set((
+                            rate.eq(currency.manual_rate.unwrap_or(new_rate)),
+                            provider_rate.eq(Some(new_rate)),
+                            last_update_day.eq(Some(day)),
+                            active.eq(is_active),
+                        ))
+                        .execute(conn)?;
+                    update.updated += 1;
+                }
+                None if is_code(code) => {
+                    diesel::insert_into(currencies)
+                        .values(Currencie {
+                            id: None,
+                            created_at: None,
+                            updated_at: None,
+                            name: code.clone(),
+                            rate: new_rate,
+                            last_update_day: Some(day),
+                            manual_rate: None,
+                            provider_rate: Some(new_rate),
+                            custom: false,
+                            display_name: None,
+                            symbol: None,
+                            minor_units: 2,
+                            active: true,
+                        })
+                        .execute(conn)?;
+                    update.added.push(code.clone());
+                }
+                None => println!("Provider sent an invalid code {:?}, skipping", code),
+            }
+        }
+
+        for currency in known.iter() {
+            if currency.active && !currency.custom && !rates.contains_key(&currency.name) {
+                diesel::update(currencies.find(currency.id))
+                    .set(active.eq(false))
+                    .execute(conn)?;
+                update.retired.push(currency.name.clone());
+            }
+        }
+
+        Ok(update)
+    })
+}
+
+// Pins the rate so rate refreshes leave it, or unpins it with None. Custom currencies only ever
+// have the rate set here.
+pub fn pin(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    currencie_id: i32,
+    new_rate: Option<f32>,
+) -> Result<Currencie, String> {
+    use crate::schema::currencies::dsl::*;
+
+    if let Some(new_rate) = new_rate {
+        check_rate(new_rate)?;
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let before = match currencies
+            .find(currencie_id)
+            .first::<Currencie>(conn)
+            .optional()?
+        {
+            Some(before) => before,
+            None => return Ok(Err(String::from("Currency not found"))),
+        };
+
+        let (pinned, effective) = match (before.custom, new_rate) {
+            (true, Some(new_rate)) => (None, new_rate),
+            (true, None) => return Ok(Err(String::from("Custom currencies need a rate"))),
+            (false, Some(new_rate)) => (Some(new_rate), new_rate),
+            (false, None) => (None, before.provider_rate.unwrap_or(before.rate)),
+        };
+
+        diesel::update(currencies.find(currencie_id))
+            .set((manual_rate.eq(pinned), rate.eq(effective)))
+            .execute(conn)?;
+
+        let after = currencies.find(currencie_id).first::<Currencie>(conn)?;
+        audit::record(conn, actor, audit::RATE, None, Some(&before), Some(&after))?;
+
+        Ok(Ok(after))
+    })
+    .map_err(|e| e.to_string())?
+}
+
+// Pins by currency code, for the CLI
+pub fn pin_by_name(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    code: &str,
+    new_rate: Option<f32>,
+) -> Result<Currencie, String> {
+    match find_by_name(conn, &code.to_uppercase()).map_err(|e| e.to_string())? {
+        Some(Currencie {
+            id: Some(found_id), ..
+        }) => pin(conn, actor, found_id, new_rate),
+        _ => Err(format!("Currency {} not found", code)),
+    }
+}
+
+pub fn overrides_of(conn: &SqliteConnection, owner_id: i32) -> QueryResult<Vec<RateOverride>> {
+    use crate::schema::rate_overrides::dsl::*;
+
+    rate_overrides
+        .filter(email_id.eq(owner_id))
+        .load::<RateOverride>(conn)
+}
+
+// Sets the email's own rate for the currency, replacing the one it had
+pub fn save_override(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    body: &RateOverride,
+) -> Result<RateOverride, String> {
+    use crate::schema::rate_overrides::dsl;
+
+    check_rate(body.rate)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let currency_exists = {
+            use crate::schema::currencies::dsl::currencies;
+            currencies
+                .find(body.currencie_id)
+                .first::<Currencie>(conn)
+                .optional()?
+                .is_some()
+        };
+        if !currency_exists {
+            return Ok(Err(String::from("Currency not found")));
+        }
+
+        let existing = dsl::rate_overrides
+            .filter(dsl::email_id.eq(body.email_id))
+            .filter(dsl::currencie_id.eq(body.currencie_id))
+            .first::<RateOverride>(conn)
+            .optional()?;
+
+        match &existing {
+            Some(existing) => {
+                diesel::update(dsl::rate_overrides.filter(dsl::id.eq(existing.id)))
+                    .set(dsl::rate.eq(body.rate))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(dsl::rate_overrides)
+                    .values(RateOverride {
+                        id: None,
+                        created_at: None,
+                        updated_at: None,
+                        ..body.clone()
+                    })
+                    .execute(conn)?;
+            }
+        }
+
+        let saved = dsl::rate_overrides
+            .filter(dsl::email_id.eq(body.email_id))
+            .filter(dsl::currencie_id.eq(body.currencie_id))
+            .first::<RateOverride>(conn)?;
+        audit::record(
+            conn,
+            actor,
+            audit::RATE,
+            Some(saved.email_id),
+            existing.as_ref(),
+            Some(&saved),
+        )?;
+
+        Ok(Ok(saved))
+    })
+    .map_err(|e| e.to_string())?
+}
+
+pub fn delete_override(
+    conn: &SqliteConnection,
+    actor: &Actor,
+    override_id: i32,
+) -> QueryResult<usize> {
+    use crate::schema::rate_overrides::dsl::*;
+
+    conn.transaction(|| {
+        let deleted = rate_overrides
+            .find(override_id)
+            .first::<RateOverride>(conn)
+            .optional()?;
+
+        match deleted {
+            Some(deleted) => {
+                let removed = diesel::delete(rate_overrides.find(override_id)).execute(conn)?;
+                audit::record(
+                    conn,
+                    actor,
+                    audit::RATE,
+                    Some(deleted.email_id),
+                    Some(&deleted),
+                    None,
+                )?;
+
+                Ok(removed)
+            }
+            None => Ok(0),
+        }
+    })
+}
+
+// The currencies with the email's own rates in place of the shared ones
+pub fn with_overrides(
+    conn: &SqliteConnection,
+    mut currencies: Vec<Currencie>,
+    owner_id: Option<i32>,
+) -> QueryResult<Vec<Currencie>> {
+    let overrides = match owner_id {
+        Some(owner_id) => overrides_of(conn, owner_id)?,
+        None => return Ok(currencies),
+    };
+
+    for currency in currencies.iter_mut() {
+        if let Some(rate_override) = overrides
+            .iter()
+            .find(|rate_override| Some(rate_override.currencie_id) == currency.id)
+        {
+            currency.rate = rate_override.rate;
+        }
+    }
+
+    Ok(currencies)
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+// The amount rounded to the currency's minor units with its symbol, like $1,234.50, ¥1,235 or
+// CHF 12.00. Codes without a symbol, or no currency at all, get the code or nothing.
+pub fn format_amount(amount: f64, currency: Option<&Currencie>) -> String {
+    let digits = currency
+        .map(|currency| currency.minor_units.clamp(0, MAX_MINOR_UNITS))
+        .unwrap_or(2) as usize;
+
+    let rounded = format!("{:.*}", digits, amount.abs());
+    let (whole, fraction) = match rounded.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (rounded.as_str(), None),
+    };
+
+    let mut number = group_thousands(whole);
+    if let Some(fraction) = fraction {
+        number.push('.');
+        number.push_str(fraction);
+    }
+
+    let is_zero = rounded.chars().all(|c| c == '0' || c == '.');
+    let sign = if amount < 0.0 && !is_zero { "-" } else { "" };
+
+    let symbol = currency.map(|currency| {
+        currency
+            .symbol
+            .clone()
+            .unwrap_or_else(|| currency.name.clone())
+    });
+
+    match symbol {
+        // Letters are set apart, like CHF 12.00
+        Some(symbol) if symbol.chars().last().is_some_and(|c| c.is_alphabetic()) => {
+            format!("{}{} {}", sign, symbol, number)
+        }
+        Some(symbol) => format!("{}{}{}", sign, symbol, number),
+        None => format!("{}{}", sign, number),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Email;
+    use crate::{establish_pool, run_migrations};
+
+    // A seeded currency as if the provider had sent the rate
+    fn currency(conn: &SqliteConnection, code: &str, provider: f32) -> Currencie {
+        use crate::schema::currencies::dsl::*;
+
+        diesel::update(currencies.filter(name.eq(code)))
+            .set((rate.eq(provider), provider_rate.eq(provider)))
+            .execute(conn)
+            .unwrap();
+
+        find_by_name(conn, code).unwrap().unwrap()
+    }
+
+    #[test]
+    fn pins_and_unpins_rates() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+        let actor = Actor::cli();
+
+        let eur = currency(&conn, "EUR", 0.9);
+        let eur_id = eur.id.unwrap();
+
+        let pinned = pin(&conn, &actor, eur_id, Some(0.8)).unwrap();
+        assert_eq!((pinned.rate, pinned.manual_rate), (0.8, Some(0.8)));
+
+        let unpinned = pin(&conn, &actor, eur_id, None).unwrap();
+        assert_eq!((unpinned.rate, unpinned.manual_rate), (0.9, None));
+
+        assert!(pin(&conn, &actor, eur_id, Some(0.0)).is_err());
+        assert!(pin_by_name(&conn, &actor, "XXX", Some(1.0)).is_err());
+    }
+
+    #[test]
+    fn creates_custom_currencies() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+        let actor = Actor::cli();
+
+        let body = |name: &str, rate: f32| CustomCurrencyBody {
+            name: name.to_string(),
+            rate,
+            display_name: None,
+            symbol: None,
+            minor_units: Some(0),
+        };
+
+        let points = create_custom(&conn, &actor, &body(" points ", 100.0)).unwrap();
+        assert_eq!((points.name.as_str(), points.custom), ("POINTS", true));
+
+        assert!(create_custom(&conn, &actor, &body("POINTS", 50.0)).is_err());
+        assert!(create_custom(&conn, &actor, &body("no spaces", 1.0)).is_err());
+        assert!(create_custom(&conn, &actor, &body("BTC", -1.0)).is_err());
+        assert!(pin(&conn, &actor, points.id.unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn overrides_only_the_emails_costs() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+        let actor = Actor::cli();
+
+        let email_id = {
+            use crate::schema::emails::dsl::*;
+            diesel::insert_into(emails)
+                .values(Email {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    email: String::from("a@x.com"),
+                    currencie_id: None,
+                    version: 0,
+                })
+                .execute(&conn)
+                .unwrap();
+            emails.select(id).first::<Option<i32>>(&conn).unwrap()
+        };
+
+        let eur = currency(&conn, "EUR", 0.9);
+        let rate_override = |rate: f32| RateOverride {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            email_id: email_id.unwrap(),
+            currencie_id: eur.id.unwrap(),
+            rate,
+        };
+
+        save_override(&conn, &actor, &rate_override(0.5)).unwrap();
+        let saved = save_override(&conn, &actor, &rate_override(0.7)).unwrap();
+        assert_eq!(overrides_of(&conn, email_id.unwrap()).unwrap().len(), 1);
+
+        let own = with_overrides(&conn, vec![eur.clone()], email_id).unwrap();
+        assert_eq!(own[0].rate, 0.7);
+        let shared = with_overrides(&conn, vec![eur.clone()], Some(999)).unwrap();
+        assert_eq!(shared[0].rate, 0.9);
+
+        assert_eq!(
+            delete_override(&conn, &actor, saved.id.unwrap()).unwrap(),
+            1
+        );
+        let own = with_overrides(&conn, vec![eur], email_id).unwrap();
+        assert_eq!(own[0].rate, 0.9);
+    }
+
+    #[test]
+    fn formats_in_minor_units() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+
+        let seeded = |code: &str| find_by_name(&conn, code).unwrap().unwrap();
+        let (usd, jpy, bhd, chf) = (seeded("USD"), seeded("JPY"), seeded("BHD"), seeded("CHF"));
+
+        assert_eq!(format_amount(1234.5, Some(&usd)), "$1,234.50");
+        assert_eq!(format_amount(-0.004, Some(&usd)), "$0.00");
+        assert_eq!(format_amount(-5.0, Some(&usd)), "-$5.00");
+        assert_eq!(format_amount(1234567.8, Some(&jpy)), "¥1,234,568");
+        assert_eq!(format_amount(12.3456, Some(&bhd)), "BD 12.346");
+        assert_eq!(format_amount(12.0, Some(&chf)), "CHF 12.00");
+        assert_eq!(format_amount(999.999, None), "1,000.00");
+
+        assert!(!seeded("VEF").active);
+        assert_eq!(seeded("EUR").display_name, Some(String::from("Euro")));
+    }
+
+    #[test]
+    fn follows_the_provider_codes() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+        let actor = Actor::cli();
+        let day = chrono::NaiveDate::from_ymd(2026, 10, 19).and_hms(0, 0, 0);
+
+        let eur_id = currency(&conn, "EUR", 0.9).id.unwrap();
+        pin(&conn, &actor, eur_id, Some(0.8)).unwrap();
+
+        let mut rates = BTreeMap::new();
+        rates.insert(String::from("EUR"), 0.95);
+        rates.insert(String::from("USD"), 1.1);
+        rates.insert(String::from("XCG"), 1.8);
+        rates.insert(String::from("bad code"), 1.0);
+        rates.insert(String::from("GBP"), 0.0);
+        rates.insert(String::from("CHF"), f64::NAN);
+
+        let update = apply_provider_rates(&conn, &rates, day).unwrap();
+        assert_eq!(update.updated, 2);
+        assert_eq!(update.added, vec![String::from("XCG")]);
+        assert!(update.retired.contains(&String::from("JPY")));
+        // Already withdrawn
+        assert!(!update.retired.contains(&String::from("VEF")));
+        assert_eq!(
+            update.rejected,
+            vec![String::from("CHF"), String::from("GBP")]
+        );
+        assert!(!update.retired.contains(&String::from("GBP")));
+
+        let found = |code: &str| find_by_name(&conn, code).unwrap().unwrap();
+        let eur = found("EUR");
+        assert_eq!((eur.rate, eur.provider_rate), (0.8, Some(0.95)));
+        let xcg = found("XCG");
+        assert!(xcg.active && !xcg.custom);
+        assert_eq!(xcg.last_update_day, Some(day));
+        let jpy = found("JPY");
+        assert!(!jpy.active);
+        assert!(find_by_name(&conn, "bad code").unwrap().is_none());
+
+        // An empty response changes nothing
+        let update = apply_provider_rates(&conn, &BTreeMap::new(), day).unwrap();
+        assert!(update.retired.is_empty());
+        assert!(found("USD").active);
+
+        // Back once provided again, unless withdrawn
+        rates.insert(String::from("JPY"), 150.0);
+        rates.insert(String::from("VEF"), 4.0);
+        apply_provider_rates(&conn, &rates, day).unwrap();
+        assert!(found("JPY").active);
+        let vef = found("VEF");
+        assert!(!vef.active);
+        assert_eq!(vef.provider_rate, Some(4.0));
+    }
+}
@@ -0,0 +1,122 @@
+use actix_web::web::Bytes;
+use diesel::prelude::*;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde_json::Value;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, RecvError};
+
+use crate::model::Subscription;
+use crate::{household, webhook};
+
+pub const RATES_REFRESHED: &str = "rates.refreshed";
+// Sent to a client that fell too far behind, it should refetch everything
+pub const RESYNC: &str = "resync";
+
+// Events a slow client can fall behind by before it's told to resync
+const CAPACITY: usize = 256;
+// Comment lines keep proxies from closing an idle stream
+const KEEP_ALIVE_SECONDS: u64 = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeEvent {
+    // None goes to everyone
+    #[serde(skip)]
+    pub email_ids: Option<Vec<i32>>,
+    pub event: String,
+    pub data: Value,
+}
+
+impl ChangeEvent {
+    fn is_for(&self, email_id: i32) -> bool {
+        self.email_ids
+            .as_ref()
+            .map(|email_ids| email_ids.contains(&email_id))
+            .unwrap_or(true)
+    }
+}
+
+fn channel() -> &'static broadcast::Sender<ChangeEvent> {
+    static CHANNEL: OnceLock<broadcast::Sender<ChangeEvent>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+// Pushes the event to the connected clients of these emails. Only reaches clients of this
+// process, e.g. not changes made from the CLI.
+pub fn publish(email_ids: Option<Vec<i32>>, event: &str, data: Value) {
+    // Fails only when nobody is listening
+    let _ = channel().send(ChangeEvent {
+        email_ids,
+        event: event.to_string(),
+        data,
+    });
+}
+
+fn frame(event: &str, data: &Value) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// The text/event-stream body for one client, starting with its own events from now on
+pub fn stream_for(email_id: i32) -> BoxStream<'static, Result<Bytes, actix_web::Error>> {
+    let changes = stream::unfold(channel().subscribe(), move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(change) if change.is_for(email_id) => {
+                    return Some((frame(&change.event, &change.data), receiver))
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => return Some((frame(RESYNC, &Value::Null), receiver)),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let keep_alive = tokio::time::interval(Duration::from_secs(KEEP_ALIVE_SECONDS))
+        .map(|_| Bytes::from_static(b": keep-alive\n\n"));
+
+    stream::once(async { Bytes::from_static(b"retry: 5000\n\n") })
+        .chain(stream::select(changes, keep_alive))
+        .map(Ok)
+        .boxed()
+}
+
+// Tells the subscription's owner and, when shared, the household's members about a change,
+// through their webhooks and live streams. Failures are logged, they never fail the change.
+pub fn subscription_changed(
+    conn: &SqliteConnection,
+    event_type: &str,
+    subscription: &Subscription,
+) {
+    let res = match subscription.household_id {
+        Some(household_id) => household::members(conn, vec![household_id]),
+        None => Ok(vec![]),
+    }
+    .and_then(|members| {
+        let mut email_ids: Vec<i32> = members.iter().map(|member| member.email_id).collect();
+        email_ids.push(subscription.email_id);
+        email_ids.sort_unstable();
+        email_ids.dedup();
+
+        let data = serde_json::to_value(subscription).unwrap_or_default();
+        publish(Some(email_ids.clone()), event_type, data.clone());
+
+        webhook::emit(conn, email_ids, event_type, None, data)
+    });
+
+    if let Err(e) = res {
+        println!("Error sending {} event: {:?}", event_type, e);
+    }
+}
+
+// Same as subscription_changed, for a subscription that's just been saved
+pub fn saved_subscription_changed(conn: &SqliteConnection, event_type: &str, subscription_id: i32) {
+    use crate::schema::subscriptions::dsl::subscriptions;
+
+    match subscriptions
+        .find(subscription_id)
+        .first::<Subscription>(conn)
+    {
+        Ok(subscription) => subscription_changed(conn, event_type, &subscription),
+        Err(e) => println!("Error sending {} event: {:?}", event_type, e),
+    }
+}
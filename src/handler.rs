@@ -1,12 +1,55 @@
-use actix_web::{client::Client, get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{
+    client::Client, delete, dev::BodyEncoding, error::BlockingError, get, http::ContentEncoding,
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{Duration, NaiveDate, Utc};
 use diesel::{r2d2::ConnectionManager, SqliteConnection};
 
 type DbPool = diesel::r2d2::Pool<ConnectionManager<SqliteConnection>>;
 
+use crate::auth::{self, AuthEmail};
 use crate::postbody::*;
-use crate::{model::*, schema};
+use crate::version::{self, IfMatch, Outcome};
+use crate::{
+    account, api_key, audit, budget, cost, currency, events, household, lifecycle, model::*, price,
+    rates, schema, session, snapshot, webhook,
+};
 use diesel::prelude::*;
 
+// Runs a permission check for the signed in email on its own connection.
+// Unknown emails and failed checks are denied.
+async fn permitted<F>(pool: &DbPool, auth_email: &AuthEmail, check: F) -> bool
+where
+    F: FnOnce(&SqliteConnection, i32) -> QueryResult<bool> + Send + 'static,
+{
+    let address = auth_email.0.clone();
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || match household::email_id_of(&conn, &address)? {
+                Some(caller_id) => check(&conn, caller_id),
+                None => Ok(false),
+            })
+            .await;
+
+            matches!(res, Ok(true))
+        }
+        _ => false,
+    }
+}
+
+// The check for an email's own routes
+fn is_self(email_id: i32) -> impl FnOnce(&SqliteConnection, i32) -> QueryResult<bool> + Send {
+    move |_, caller_id| Ok(caller_id == email_id)
+}
+
+// Like is_self, for routes that name the email by its address
+fn is_self_named(
+    address: String,
+) -> impl FnOnce(&SqliteConnection, i32) -> QueryResult<bool> + Send {
+    move |conn, caller_id| Ok(household::email_id_of(conn, &address)? == Some(caller_id))
+}
+
 // EMAILS
 
 #[get("/emails")]
@@ -35,10 +78,15 @@ struct EmailNameInfo {
 #[get("/emails/byname")]
 async fn get_email_by_name(
     pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
     email_name: web::Query<EmailNameInfo>,
 ) -> impl Responder {
     println!("Getting email by name! {}", email_name.name);
 
+    if !permitted(&pool, &auth_email, is_self_named(email_name.name.clone())).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this email");
+    }
+
     match pool.get() {
         Ok(conn) => {
             use crate::schema::emails::dsl::{email, emails};
@@ -49,7 +97,9 @@ async fn get_email_by_name(
                 web::block(move || emails.filter(email.eq(email_clone)).first::<Email>(&conn))
                     .await;
             match emails_res {
-                Ok(email_get) => HttpResponse::Ok().json(&email_get),
+                Ok(email_get) => HttpResponse::Ok()
+                    .header("etag", version::etag(email_get.version))
+                    .json(&email_get),
                 _ => HttpResponse::InternalServerError()
                     .body(format!("Error getting email! {}", email_name.name)),
             }
@@ -59,19 +109,26 @@ async fn get_email_by_name(
 }
 
 #[get("/emails/{email_id}")]
-async fn get_email(pool: web::Data<DbPool>, email_id: web::Path<i32>) -> impl Responder {
+async fn get_email(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this email");
+    }
+
     match pool.get() {
         Ok(conn) => {
             use crate::schema::emails::dsl::{emails, id};
-            let email_res = web::block(move || {
-                emails
-                    .filter(id.eq(email_id.into_inner()))
-                    .first::<Email>(&conn)
-            })
-            .await;
+            let email_res =
+                web::block(move || emails.filter(id.eq(email_id)).first::<Email>(&conn)).await;
 
             match email_res {
-                Ok(email) => HttpResponse::Ok().json(&email),
+                Ok(email) => HttpResponse::Ok()
+                    .header("etag", version::etag(email.version))
+                    .json(&email),
                 _ => HttpResponse::InternalServerError().body("Error getting email!"),
             }
         }
@@ -79,23 +136,63 @@ async fn get_email(pool: web::Data<DbPool>, email_id: web::Path<i32>) -> impl Re
     }
 }
 
+// Filters for subscription lists. status matches the stored status, active keeps those
+// billed right now (a paused subscription past paused_until is active again).
+#[derive(Deserialize)]
+struct SubscriptionsQuery {
+    status: Option<String>,
+    active: Option<bool>,
+}
+
+impl SubscriptionsQuery {
+    fn apply(&self, subscriptions_list: Vec<Subscription>) -> Vec<Subscription> {
+        let now = Utc::now().naive_utc();
+
+        subscriptions_list
+            .into_iter()
+            .filter(|subscription| match &self.status {
+                Some(status) => &subscription.status == status,
+                None => true,
+            })
+            .filter(|subscription| match self.active {
+                Some(active) => lifecycle::is_active_at(subscription, now) == active,
+                None => true,
+            })
+            .collect()
+    }
+}
+
 #[get("/emails/{email_id}/subscriptions")]
 async fn get_email_subscriptions(
     pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
     email_id: web::Path<i32>,
+    subscriptions_query: web::Query<SubscriptionsQuery>,
 ) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these subscriptions");
+    }
+
     match pool.get() {
         Ok(conn) => {
             let subscriptions_res = web::block(move || {
-                use crate::schema::emails::dsl::{emails, id};
+                use crate::schema::emails::dsl::emails;
+                use crate::schema::subscriptions::dsl::{
+                    email_id as subscription_email_id, subscriptions,
+                };
 
-                let email = emails.find(email_id.into_inner()).first::<Email>(&conn)?;
-                Subscription::belonging_to(&email).load::<Subscription>(&conn)
+                let email = emails.find(email_id).first::<Email>(&conn)?;
+                subscriptions
+                    .filter(subscription_email_id.nullable().eq(email.id))
+                    .load::<Subscription>(&conn)
             })
             .await;
 
             match subscriptions_res {
-                Ok(subscriptions_list) => HttpResponse::Ok().json(&subscriptions_list),
+                Ok(subscriptions_list) => {
+                    HttpResponse::Ok().json(subscriptions_query.apply(subscriptions_list))
+                }
                 _ => HttpResponse::InternalServerError().body("Getting subscriptions list error!"),
             }
         }
@@ -106,22 +203,33 @@ async fn get_email_subscriptions(
 #[get("/emails/byname/{email_name}/subscriptions")]
 async fn get_email_by_name_subscriptions(
     pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
     email_name: web::Path<String>,
+    subscriptions_query: web::Query<SubscriptionsQuery>,
 ) -> impl Responder {
+    if !permitted(&pool, &auth_email, is_self_named(email_name.clone())).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these subscriptions");
+    }
+
     match pool.get() {
         Ok(conn) => {
             let subscriptions_res = web::block(move || {
-                use crate::schema::emails::dsl::{email, emails, id};
+                use crate::schema::emails::dsl::{email, emails};
+                use crate::schema::subscriptions::dsl::{email_id, subscriptions};
 
                 let email_found = emails
                     .filter(email.eq(email_name.into_inner()))
                     .first::<Email>(&conn)?;
-                Subscription::belonging_to(&email_found).load::<Subscription>(&conn)
+                subscriptions
+                    .filter(email_id.nullable().eq(email_found.id))
+                    .load::<Subscription>(&conn)
             })
             .await;
 
             match subscriptions_res {
-                Ok(subscriptions_list) => HttpResponse::Ok().json(&subscriptions_list),
+                Ok(subscriptions_list) => {
+                    HttpResponse::Ok().json(subscriptions_query.apply(subscriptions_list))
+                }
                 _ => HttpResponse::Ok().json(&vec![] as &Vec<Subscription>),
             }
         }
@@ -130,21 +238,69 @@ async fn get_email_by_name_subscriptions(
 }
 
 #[post("/emails")]
-async fn post_email(pool: web::Data<DbPool>, email_body: web::Json<Email>) -> impl Responder {
+async fn post_email(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    if_match: Option<IfMatch>,
+    email_body: web::Json<Email>,
+) -> impl Responder {
+    if email_body.id.is_some() && if_match.is_none() {
+        return version::required();
+    }
+    // The address is who signed in, so it can be created or kept but not changed to another
+    if email_body.email.trim().to_lowercase() != auth_email.0.trim().to_lowercase() {
+        return HttpResponse::Forbidden().body("Emails can only be saved for yourself");
+    }
+    if let Some(email_id) = email_body.id {
+        if !permitted(&pool, &auth_email, is_self(email_id)).await {
+            return HttpResponse::Forbidden().body("Emails can only be saved for yourself");
+        }
+    }
+
     match pool.get() {
         Ok(conn) => {
             let res = web::block(move || {
-                use crate::schema::emails::dsl::{emails, id};
-                diesel::replace_into(emails)
-                    .values(&email_body.into_inner())
-                    .execute(&conn);
+                use crate::schema::emails::dsl::{email, emails};
 
-                emails.order(id.desc()).first::<Email>(&conn)
+                let email_body = email_body.into_inner();
+
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let stored = match email_body.id {
+                        Some(email_id) => emails.find(email_id).first::<Email>(&conn).optional()?,
+                        None => None,
+                    };
+
+                    if let (Some(stored), Some(if_match)) = (&stored, &if_match) {
+                        if !if_match.matches(stored.version) {
+                            return Ok(Outcome::Conflict(stored.clone()));
+                        }
+                    }
+
+                    email_body.save(&conn)?;
+
+                    let saved = emails
+                        .filter(email.eq(&email_body.email))
+                        .first::<Email>(&conn)?;
+                    audit::record(
+                        &conn,
+                        &actor,
+                        audit::EMAIL,
+                        saved.id,
+                        stored.as_ref(),
+                        Some(&saved),
+                    )?;
+
+                    Ok(Outcome::Saved(saved))
+                })
             })
             .await;
 
             match res {
-                Ok(email) => HttpResponse::Created().json(email),
+                Ok(Outcome::Saved(email)) => HttpResponse::Created()
+                    .header("etag", version::etag(email.version))
+                    .json(email),
+                Ok(Outcome::Conflict(email)) => version::conflict(email.version, &email),
                 _ => HttpResponse::InternalServerError().body("Error replacing email!"),
             }
         }
@@ -160,6 +316,7 @@ struct EmailSaveBody {
 #[post("/emails/save")]
 async fn post_email_save(
     pool: web::Data<DbPool>,
+    actor: audit::Actor,
     email_body: web::Json<EmailSaveBody>,
 ) -> impl Responder {
     match pool.get() {
@@ -173,20 +330,30 @@ async fn post_email_save(
 
                 match &found_email {
                     Ok(email_res) => found_email,
-                    _ => {
-                        diesel::replace_into(emails)
+                    _ => conn.transaction(|| {
+                        diesel::insert_into(emails)
                             .values(&Email {
                                 id: None,
-                                email: Some(email_body.name.clone()),
+                                email: email_body.name.clone(),
                                 created_at: None,
-                                currency_id: None,
                                 currencie_id: None,
                                 updated_at: None,
+                                version: 0,
                             })
-                            .execute(&conn);
+                            .execute(&conn)?;
 
-                        emails.order(id.desc()).first::<Email>(&conn)
-                    }
+                        let created = emails.order(id.desc()).first::<Email>(&conn)?;
+                        audit::record(
+                            &conn,
+                            &actor,
+                            audit::EMAIL,
+                            created.id,
+                            None,
+                            Some(&created),
+                        )?;
+
+                        Ok(created)
+                    }),
                 }
             })
             .await;
@@ -199,78 +366,210 @@ async fn post_email_save(
         _ => HttpResponse::InternalServerError().body("Getting connection error!"),
     }
 }
+// What a client last loaded, sent back when its save conflicts
+#[derive(Serialize)]
+struct EmailState {
+    email: Email,
+    subscriptions: Vec<Subscription>,
+}
+
+// The current state when the email or any subscription being saved changed since the client
+// loaded it. If-Match is the email's version, subscriptions carry theirs in the body.
+fn save_bulk_conflict(
+    conn: &SqliteConnection,
+    if_match: &IfMatch,
+    email_body: &EmailPostBody,
+) -> QueryResult<Option<EmailState>> {
+    let stored_email = {
+        use crate::schema::emails::dsl::*;
+        emails
+            .filter(id.eq(email_body.email.id))
+            .first::<Email>(conn)?
+    };
+
+    let stored_subscriptions = {
+        use crate::schema::subscriptions::dsl::*;
+        subscriptions
+            .filter(
+                id.eq_any(
+                    email_body
+                        .subscriptions
+                        .iter()
+                        .map(|subscription| subscription.id),
+                ),
+            )
+            .load::<Subscription>(conn)?
+    };
+
+    let stale = email_body.subscriptions.iter().any(|subscription| {
+        subscription.id.is_some()
+            && !stored_subscriptions.iter().any(|stored| {
+                stored.id == subscription.id && stored.version == subscription.version
+            })
+    });
+
+    if if_match.matches(stored_email.version) && !stale {
+        return Ok(None);
+    }
+
+    use crate::schema::subscriptions::dsl::*;
+    Ok(Some(EmailState {
+        subscriptions: subscriptions
+            .filter(email_id.nullable().eq(stored_email.id))
+            .load::<Subscription>(conn)?,
+        email: stored_email,
+    }))
+}
+
 #[post("/emails/save-bulk")]
 async fn post_email_save_bulk(
     pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    if_match: IfMatch,
     email_body: web::Json<EmailPostBody>,
 ) -> impl Responder {
+    // Only the signed in email, and subscriptions it may edit
+    let saved_email = email_body.email.clone();
+    let saved_subscriptions = email_body.subscriptions.clone();
+    let delete_ids = email_body.subscription_delete_ids.clone();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        if saved_email.id != Some(caller_id) {
+            return Ok(false);
+        }
+        for subscription in &saved_subscriptions {
+            if !household::can_save_subscription(conn, subscription, caller_id)? {
+                return Ok(false);
+            }
+        }
+        for subscription_id in delete_ids {
+            if !household::can_edit_subscription(conn, subscription_id, caller_id)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit these subscriptions");
+    }
+
     // println!("{:#?}", email_body);
 
     match pool.get() {
         Ok(conn) => {
             let res = web::block(move || {
-                // Save email
-                {
-                    use crate::schema::emails::dsl::*;
-                    diesel::replace_into(emails)
-                        .values(&email_body.email)
-                        .execute(&conn);
-                }
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    if let Some(current) = save_bulk_conflict(&conn, &if_match, &email_body)? {
+                        return Ok(Outcome::Conflict(current));
+                    }
 
-                // Save subscriptions
-                {
-                    use crate::schema::subscriptions::dsl::*;
+                    let email_before = {
+                        use crate::schema::emails::dsl::*;
+                        emails
+                            .filter(id.eq(email_body.email.id))
+                            .first::<Email>(&conn)?
+                    };
 
-                    email_body.subscriptions.iter().for_each(|subscription| {
-                        println!("{:?}", subscription);
+                    // Save email
+                    email_body.email.save(&conn)?;
+
+                    // Save subscriptions
+                    for subscription in email_body.subscriptions.iter() {
+                        use crate::schema::subscriptions::dsl::*;
 
                         // Check subscriptions with email id
-                        let subs_count: Result<Vec<Subscription>, _> = subscriptions
-                            .filter(email_id.eq(email_body.email.id))
-                            .load::<Subscription>(&conn);
-
-                        match subs_count {
-                            Ok(subs) => {
-                                println!("Subs count: {}", subs.len());
-
-                                if subs.len() <= 100 {
-                                    diesel::replace_into(subscriptions)
-                                        .values(subscription)
-                                        .execute(&conn);
-                                }
-                            }
-                            _ => {
-                                println!("Error gettting subs");
-                            }
+                        let subs_count = subscriptions
+                            .filter(email_id.nullable().eq(email_body.email.id))
+                            .count()
+                            .get_result::<i64>(&conn)?;
+                        if subs_count > 100 {
+                            continue;
                         }
-                    });
-                }
 
-                // Delete unwanted subscriptions
-                {
-                    use crate::schema::subscriptions::dsl::*;
+                        let before = subscriptions
+                            .filter(id.eq(subscription.id))
+                            .first::<Subscription>(&conn)
+                            .optional()?;
 
-                    email_body
-                        .subscription_delete_ids
-                        .iter()
-                        .for_each(|subscription_id| {
-                            println!("Delete IDs: {:?}", subscription_id);
+                        let saved_id = price::save_subscription(&conn, subscription)?;
+                        let saved = subscriptions.find(saved_id).first::<Subscription>(&conn)?;
+                        audit::record(
+                            &conn,
+                            &actor,
+                            audit::SUBSCRIPTION,
+                            Some(saved.email_id),
+                            before.as_ref(),
+                            Some(&saved),
+                        )?;
 
-                            diesel::delete(subscriptions.filter(id.eq(subscription_id)))
-                                .execute(&conn);
-                        });
-                }
+                        events::saved_subscription_changed(
+                            &conn,
+                            match subscription.id {
+                                Some(_) => webhook::SUBSCRIPTION_UPDATED,
+                                None => webhook::SUBSCRIPTION_CREATED,
+                            },
+                            saved_id,
+                        )
+                    }
 
-                // Get saved email
-                {
-                    use crate::schema::emails::dsl::*;
-                    emails.filter(id.eq(email_body.email.id)).first::<Email>(&conn)
-                }
+                    // Delete unwanted subscriptions
+                    for subscription_id in email_body.subscription_delete_ids.iter() {
+                        let deleted = {
+                            use crate::schema::subscriptions::dsl::subscriptions;
+                            subscriptions
+                                .find(*subscription_id)
+                                .first::<Subscription>(&conn)?
+                        };
+
+                        Subscription::delete(&conn, *subscription_id)?;
+                        audit::record(
+                            &conn,
+                            &actor,
+                            audit::SUBSCRIPTION,
+                            Some(deleted.email_id),
+                            Some(&deleted),
+                            None,
+                        )?;
+                        events::subscription_changed(
+                            &conn,
+                            webhook::SUBSCRIPTION_DELETED,
+                            &deleted,
+                        );
+                    }
+
+                    if let Some(saved_email_id) = email_body.email.id {
+                        budget::after_change(&conn, vec![saved_email_id], "subscription");
+                    }
+
+                    // Get saved email
+                    let saved = {
+                        use crate::schema::emails::dsl::*;
+                        emails
+                            .filter(id.eq(email_body.email.id))
+                            .first::<Email>(&conn)?
+                    };
+                    audit::record(
+                        &conn,
+                        &actor,
+                        audit::EMAIL,
+                        saved.id,
+                        Some(&email_before),
+                        Some(&saved),
+                    )?;
+
+                    Ok(Outcome::Saved(saved))
+                })
             })
             .await;
 
             match res {
-                Ok(body) => HttpResponse::Created().json(body),
+                Ok(Outcome::Saved(body)) => HttpResponse::Created()
+                    .header("etag", version::etag(body.version))
+                    .json(body),
+                Ok(Outcome::Conflict(current)) => {
+                    version::conflict(current.email.version, &current)
+                }
                 _ => HttpResponse::InternalServerError().body("Error replacing email!"),
             }
         }
@@ -301,20 +600,32 @@ async fn get_subscriptions(pool: web::Data<DbPool>) -> impl Responder {
 #[get("/subscriptions/{subscription_id}")]
 async fn get_subscription(
     pool: web::Data<DbPool>,
-    subscription_id: web::Query<i32>,
+    auth_email: AuthEmail,
+    subscription_id: web::Path<i32>,
 ) -> impl Responder {
+    let subscription_id = subscription_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_view_subscription(conn, subscription_id, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to see this subscription");
+    }
+
     match pool.get() {
         Ok(conn) => {
             let subscription_res = web::block(move || {
                 use crate::schema::subscriptions::dsl::*;
                 subscriptions
-                    .find(subscription_id.into_inner())
+                    .find(subscription_id)
                     .first::<Subscription>(&conn)
             })
             .await;
 
             match subscription_res {
-                Ok(subscription) => HttpResponse::Ok().json(subscription),
+                Ok(subscription) => HttpResponse::Ok()
+                    .header("etag", version::etag(subscription.version))
+                    .json(subscription),
                 _ => HttpResponse::InternalServerError().body("Error fetching subscriptions"),
             }
         }
@@ -325,20 +636,84 @@ async fn get_subscription(
 #[post("/subscriptions")]
 async fn post_subscription(
     pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    if_match: Option<IfMatch>,
     subscription: web::Json<Subscription>,
 ) -> impl Responder {
+    if subscription.id.is_some() && if_match.is_none() {
+        return version::required();
+    }
+    if subscription.id.is_none() && subscription.household_id.is_some() {
+        return HttpResponse::BadRequest()
+            .body("Share with POST /subscriptions/{id}/shares after creating it");
+    }
+
+    let saved_subscription = subscription.clone();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_save_subscription(conn, &saved_subscription, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this subscription");
+    }
+
     match pool.get() {
         Ok(conn) => {
             let res = web::block(move || {
-                use crate::schema::subscriptions::dsl::*;
-                diesel::replace_into(subscriptions)
-                    .values(&subscription.into_inner())
-                    .execute(&conn)
+                use crate::schema::subscriptions::dsl::subscriptions;
+
+                let subscription = subscription.into_inner();
+                let saved = conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let stored = match subscription.id {
+                        Some(subscription_id) => subscriptions
+                            .find(subscription_id)
+                            .first::<Subscription>(&conn)
+                            .optional()?,
+                        None => None,
+                    };
+
+                    if let (Some(stored), Some(if_match)) = (&stored, &if_match) {
+                        if !if_match.matches(stored.version) {
+                            return Ok(Outcome::Conflict(stored.clone()));
+                        }
+                    }
+
+                    let saved_id = price::save_subscription(&conn, &subscription)?;
+                    let saved = subscriptions.find(saved_id).first::<Subscription>(&conn)?;
+                    audit::record(
+                        &conn,
+                        &actor,
+                        audit::SUBSCRIPTION,
+                        Some(saved.email_id),
+                        stored.as_ref(),
+                        Some(&saved),
+                    )?;
+
+                    Ok(Outcome::Saved(saved))
+                })?;
+
+                if let Outcome::Saved(saved) = &saved {
+                    budget::after_change(&conn, vec![saved.email_id], "subscription");
+                    events::subscription_changed(
+                        &conn,
+                        match subscription.id {
+                            Some(_) => webhook::SUBSCRIPTION_UPDATED,
+                            None => webhook::SUBSCRIPTION_CREATED,
+                        },
+                        saved,
+                    );
+                }
+
+                Ok::<_, diesel::result::Error>(saved)
             })
             .await;
 
             match res {
-                Ok(_) => HttpResponse::Created().body("OK"),
+                Ok(Outcome::Saved(saved)) => HttpResponse::Created()
+                    .header("etag", version::etag(saved.version))
+                    .body("OK"),
+                Ok(Outcome::Conflict(stored)) => version::conflict(stored.version, &stored),
                 _ => HttpResponse::InternalServerError().body("Error replacing subscription"),
             }
         }
@@ -351,52 +726,1974 @@ struct IdTokenBody {
     id_token: String,
 }
 
-#[post("/google-login-verify")]
-pub async fn google_login_verify(id_token_body: web::Json<IdTokenBody>) -> impl Responder {
+// Starts a session for a verified email
+async fn start_session(pool: &DbPool, email: String) -> HttpResponse {
+    match pool.get() {
+        Ok(conn) => {
+            let settings = auth::settings();
+            let res = web::block(move || {
+                session::start(&conn, &settings.session_secret, settings.lifetimes, &email)
+            })
+            .await;
+
+            match res {
+                Ok(session_tokens) => HttpResponse::Ok().json(session_tokens),
+                _ => HttpResponse::InternalServerError().body("Error starting session"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+async fn verify_login(pool: &DbPool, id_token: &str) -> HttpResponse {
     println!("Verifying login!");
 
-    let url = format!(
-        "https://oauth2.googleapis.com/tokeninfo?id_token={}",
-        id_token_body.id_token
-    );
-    #[derive(Serialize, Deserialize, Debug)]
-    pub struct OauthResponseBody {
-        email: String,
-    }
-
-    match reqwest::get(url.as_str()).await {
-        Ok(resp) => match resp.json::<OauthResponseBody>().await {
-            Ok(oauth_response) => HttpResponse::Ok().json(OauthResponseBody {
-                email: oauth_response.email,
-            }),
-            _ => HttpResponse::InternalServerError().body("Error getting email!"),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("{:?}", e)),
+    // A key can't be traded for a session, that would lift read only
+    if api_key::is_api_key(id_token) {
+        return HttpResponse::BadRequest().body("API keys can't log in");
+    }
+
+    match auth::verify(Some(pool), id_token).await {
+        Some(email) => start_session(pool, email).await,
+        None => HttpResponse::InternalServerError().body("Error getting email!"),
     }
 }
 
-// Currencies
-#[get("/currencies")]
-async fn get_currencies(req: HttpRequest, pool: web::Data<DbPool>) -> impl Responder {
-    // println!("Currency headers: {:?}", req.headers());
+// Trades a Google id token for a session
+#[post("/google-login-verify")]
+pub async fn google_login_verify(
+    pool: web::Data<DbPool>,
+    id_token_body: web::Json<IdTokenBody>,
+) -> impl Responder {
+    verify_login(&pool, &id_token_body.id_token).await
+}
+
+// Same as google-login-verify for any provider's id token
+#[post("/login-verify")]
+pub async fn login_verify(
+    pool: web::Data<DbPool>,
+    id_token_body: web::Json<IdTokenBody>,
+) -> impl Responder {
+    verify_login(&pool, &id_token_body.id_token).await
+}
+
+// What the login page can offer
+#[get("/login-providers")]
+pub async fn get_login_providers() -> impl Responder {
+    let settings = auth::settings();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "google": true,
+        "oidc": settings.oidc,
+        "local_accounts": settings.local_accounts,
+    }))
+}
+
+#[post("/login")]
+pub async fn post_login(
+    pool: web::Data<DbPool>,
+    login_body: web::Json<account::LoginBody>,
+) -> impl Responder {
+    if !auth::settings().local_accounts {
+        return HttpResponse::NotFound().body("Local accounts are disabled");
+    }
+
     match pool.get() {
         Ok(conn) => {
+            let login_body = login_body.into_inner();
             let res = web::block(move || {
-                use crate::schema::currencies::dsl::*;
+                account::login(&conn, &login_body.username, &login_body.password)
+            })
+            .await;
+
+            match res {
+                Ok(email) => start_session(&pool, email).await,
+                Err(BlockingError::Error(e)) => HttpResponse::Unauthorized().body(e),
+                _ => HttpResponse::InternalServerError().body("Error logging in"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
 
-                currencies.load::<Currencie>(&conn)
+#[post("/sessions/refresh")]
+pub async fn post_session_refresh(
+    pool: web::Data<DbPool>,
+    refresh_body: web::Json<session::RefreshBody>,
+) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let settings = auth::settings();
+            let res = web::block(move || {
+                session::refresh(
+                    &conn,
+                    &settings.session_secret,
+                    settings.lifetimes,
+                    &refresh_body.refresh_token,
+                )
             })
             .await;
+
             match res {
-                Ok(currencies) => HttpResponse::Ok().json(currencies),
-                _ => HttpResponse::InternalServerError().body("Error fetching currencies"),
+                Ok(session_tokens) => HttpResponse::Ok().json(session_tokens),
+                Err(BlockingError::Error(e)) => HttpResponse::Unauthorized().body(e),
+                _ => HttpResponse::InternalServerError().body("Error refreshing session"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogoutQuery {
+    // Every session of the email, not just this one
+    all: Option<bool>,
+}
+
+#[post("/logout")]
+pub async fn post_logout(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    req: HttpRequest,
+    logout_query: web::Query<LogoutQuery>,
+) -> impl Responder {
+    // Google and OpenID Connect id tokens have no session to end
+    let session_id =
+        auth::id_token(req.headers(), req.path(), req.query_string()).and_then(|id_token| {
+            session::verify_access_token(&auth::settings().session_secret, &id_token)
+        });
+
+    match pool.get() {
+        Ok(conn) => {
+            let all = logout_query.all.unwrap_or(false);
+            let res = web::block(move || {
+                if all {
+                    session::revoke_all(&conn, &auth_email.0)
+                } else {
+                    match session_id {
+                        Some((_, session_id)) => session::revoke(&conn, session_id),
+                        None => Ok(0),
+                    }
+                }
+            })
+            .await;
+
+            match res {
+                Ok(_) => HttpResponse::NoContent().finish(),
+                _ => HttpResponse::InternalServerError().body("Error logging out"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[post("/subscriptions/{subscription_id}/status")]
+async fn post_subscription_status(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    subscription_id: web::Path<i32>,
+    if_match: IfMatch,
+    status_change: web::Json<lifecycle::StatusChange>,
+) -> impl Responder {
+    let edited_id = *subscription_id;
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_edit_subscription(conn, edited_id, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::subscriptions::dsl::*;
+
+                let subscription = subscriptions
+                    .find(subscription_id.into_inner())
+                    .first::<Subscription>(&conn)
+                    .map_err(|e| e.to_string())?;
+
+                if !if_match.matches(subscription.version) {
+                    return Ok(Outcome::Conflict(subscription));
+                }
+
+                let updated =
+                    lifecycle::transition(&subscription, &status_change, Utc::now().naive_utc())?;
+
+                // Only applies if nothing changed it since it was read
+                let changed = diesel::update(
+                    subscriptions
+                        .find(subscription.id)
+                        .filter(version.eq(subscription.version)),
+                )
+                .set((
+                    status.eq(&updated.status),
+                    paused_until.eq(updated.paused_until),
+                    cancelled_at.eq(updated.cancelled_at),
+                    status_changed_at.eq(updated.status_changed_at),
+                ))
+                .execute(&conn)
+                .map_err(|e| e.to_string())?;
+
+                let current = subscriptions
+                    .find(subscription.id)
+                    .first::<Subscription>(&conn)
+                    .map_err(|e| e.to_string())?;
+
+                if changed == 0 {
+                    return Ok(Outcome::Conflict(current));
+                }
+
+                audit::record(
+                    &conn,
+                    &actor,
+                    audit::SUBSCRIPTION,
+                    Some(current.email_id),
+                    Some(&subscription),
+                    Some(&current),
+                )
+                .map_err(|e| e.to_string())?;
+
+                budget::after_change(&conn, vec![current.email_id], "subscription");
+                events::subscription_changed(&conn, webhook::SUBSCRIPTION_UPDATED, &current);
+
+                Ok::<_, String>(Outcome::Saved(current))
+            })
+            .await;
+
+            match res {
+                Ok(Outcome::Saved(subscription)) => HttpResponse::Ok()
+                    .header("etag", version::etag(subscription.version))
+                    .json(subscription),
+                Ok(Outcome::Conflict(current)) => version::conflict(current.version, &current),
+                Err(BlockingError::Error(e)) => HttpResponse::BadRequest().body(e),
+                _ => HttpResponse::InternalServerError().body("Error changing subscription status"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[get("/subscriptions/{subscription_id}/prices")]
+async fn get_subscription_prices(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    subscription_id: web::Path<i32>,
+) -> impl Responder {
+    let subscription_id = subscription_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_view_subscription(conn, subscription_id, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to see this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let prices_res =
+                web::block(move || price::subscription_prices(&conn, vec![subscription_id])).await;
+
+            match prices_res {
+                Ok(prices) => HttpResponse::Ok().json(prices),
+                _ => HttpResponse::InternalServerError().body("Error fetching prices"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// CATEGORIES
+#[get("/emails/{email_id}/categories")]
+async fn get_email_categories(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these categories");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let categories_res = web::block(move || {
+                use crate::schema::categories::dsl;
+                dsl::categories
+                    .filter(dsl::email_id.eq(email_id))
+                    .load::<Category>(&conn)
+            })
+            .await;
+
+            match categories_res {
+                Ok(categories_list) => HttpResponse::Ok().json(categories_list),
+                _ => HttpResponse::InternalServerError().body("Error fetching categories"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Whether the stored category, if there is one, belongs to the email
+fn can_edit_category(
+    conn: &SqliteConnection,
+    category_id: Option<i32>,
+    caller_id: i32,
+) -> QueryResult<bool> {
+    match category_id {
+        Some(category_id) => {
+            use crate::schema::categories::dsl::categories;
+            let stored = categories
+                .find(category_id)
+                .first::<Category>(conn)
+                .optional()?;
+
+            Ok(stored
+                .map(|stored| stored.email_id == caller_id)
+                .unwrap_or(true))
+        }
+        None => Ok(true),
+    }
+}
+
+#[post("/categories")]
+async fn post_category(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    category: web::Json<Category>,
+) -> impl Responder {
+    let saved_category = category.clone();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(saved_category.email_id == caller_id
+            && can_edit_category(conn, saved_category.id, caller_id)?)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this category");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::categories::dsl::*;
+
+                let category = category.into_inner();
+                category.save(&conn)?;
+
+                categories
+                    .filter(email_id.eq(category.email_id))
+                    .filter(name.eq(category.name))
+                    .first::<Category>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(category) => HttpResponse::Created().json(category),
+                _ => HttpResponse::InternalServerError().body("Error saving category"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/categories/{category_id}")]
+async fn delete_category(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    category_id: web::Path<i32>,
+) -> impl Responder {
+    let deleted_id = *category_id;
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        can_edit_category(conn, Some(deleted_id), caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this category");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let category_id = category_id.into_inner();
+
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    {
+                        use crate::schema::budgets::dsl;
+                        for category_budget in dsl::budgets
+                            .filter(dsl::category_id.eq(category_id))
+                            .load::<Budget>(&conn)?
+                        {
+                            budget::delete_budget(&conn, category_budget.id.unwrap_or(0))?;
+                            audit::record(
+                                &conn,
+                                &actor,
+                                audit::BUDGET,
+                                Some(category_budget.email_id),
+                                Some(&category_budget),
+                                None,
+                            )?;
+                        }
+                    }
+                    {
+                        use crate::schema::subscriptions::dsl;
+                        for before in dsl::subscriptions
+                            .filter(dsl::category_id.eq(category_id))
+                            .load::<Subscription>(&conn)?
+                        {
+                            diesel::update(&before)
+                                .set(dsl::category_id.eq(None as Option<i32>))
+                                .execute(&conn)?;
+
+                            let after = dsl::subscriptions
+                                .find(before.id)
+                                .first::<Subscription>(&conn)?;
+                            audit::record(
+                                &conn,
+                                &actor,
+                                audit::SUBSCRIPTION,
+                                Some(before.email_id),
+                                Some(&before),
+                                Some(&after),
+                            )?;
+                        }
+                    }
+
+                    use crate::schema::categories::dsl::categories;
+                    diesel::delete(categories.find(category_id)).execute(&conn)
+                })
+            })
+            .await;
+
+            match res {
+                Ok(_) => HttpResponse::Ok().body("OK"),
+                _ => HttpResponse::InternalServerError().body("Error deleting category"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// TAGS
+#[get("/emails/{email_id}/tags")]
+async fn get_email_tags(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these tags");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let tags_res = web::block(move || {
+                use crate::schema::{subscriptions, tags};
+
+                tags::table
+                    .inner_join(subscriptions::table)
+                    .filter(subscriptions::email_id.eq(email_id))
+                    .select(tags::all_columns)
+                    .load::<Tag>(&conn)
+            })
+            .await;
+
+            match tags_res {
+                Ok(tags_list) => HttpResponse::Ok().json(tags_list),
+                _ => HttpResponse::InternalServerError().body("Error fetching tags"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[post("/tags")]
+async fn post_tag(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    tag: web::Json<Tag>,
+) -> impl Responder {
+    let tagged_id = tag.subscription_id;
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_edit_subscription(conn, tagged_id, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::tags::dsl::*;
+
+                let tag = Tag {
+                    name: tag.name.trim().to_string(),
+                    ..tag.into_inner()
+                };
+
+                diesel::insert_or_ignore_into(tags)
+                    .values(&tag)
+                    .execute(&conn)?;
+
+                tags.filter(subscription_id.eq(tag.subscription_id))
+                    .filter(name.eq(tag.name))
+                    .first::<Tag>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(tag) => HttpResponse::Created().json(tag),
+                _ => HttpResponse::InternalServerError().body("Error saving tag"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/tags/{tag_id}")]
+async fn delete_tag(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    tag_id: web::Path<i32>,
+) -> impl Responder {
+    let deleted_id = *tag_id;
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        use crate::schema::tags::dsl::tags;
+        match tags.find(deleted_id).first::<Tag>(conn).optional()? {
+            Some(tag) => household::can_edit_subscription(conn, tag.subscription_id, caller_id),
+            None => Ok(true),
+        }
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to edit this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::tags::dsl::tags;
+                diesel::delete(tags.find(tag_id.into_inner())).execute(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(_) => HttpResponse::Ok().body("OK"),
+                _ => HttpResponse::InternalServerError().body("Error deleting tag"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// BREAKDOWN
+#[get("/emails/{email_id}/breakdown")]
+async fn get_email_breakdown(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this breakdown");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::emails::dsl::emails;
+
+                let email = emails.find(email_id).first::<Email>(&conn)?;
+                cost::breakdown(&conn, email)
+            })
+            .await;
+
+            match res {
+                Ok(breakdown) => HttpResponse::Ok().json(breakdown),
+                _ => HttpResponse::InternalServerError().body("Error calculating breakdown"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// HISTORY
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[get("/emails/{email_id}/history")]
+async fn get_email_history(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+    history_query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this history");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::spending_snapshots::dsl;
+
+                let mut query = dsl::spending_snapshots
+                    .filter(dsl::email_id.eq(email_id))
+                    .into_boxed();
+
+                if let Some(from) = history_query.from {
+                    query = query.filter(dsl::snapshot_day.ge(from.and_hms(0, 0, 0)));
+                }
+                if let Some(to) = history_query.to {
+                    query = query.filter(dsl::snapshot_day.le(to.and_hms(0, 0, 0)));
+                }
+
+                query
+                    .order(dsl::snapshot_day.asc())
+                    .load::<SpendingSnapshot>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(snapshots) => HttpResponse::Ok().json(snapshot::history(snapshots)),
+                _ => HttpResponse::InternalServerError().body("Error fetching history"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// PAID
+#[derive(Deserialize)]
+struct PaidQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+// Defaults to the past year
+#[get("/emails/{email_id}/paid")]
+async fn get_email_paid(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+    paid_query: web::Query<PaidQuery>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this spending");
+    }
+
+    let to = paid_query
+        .to
+        .unwrap_or_else(|| Utc::now().naive_utc().date())
+        .and_hms(0, 0, 0);
+    let from = paid_query
+        .from
+        .map(|from| from.and_hms(0, 0, 0))
+        .unwrap_or_else(|| to - Duration::days(365));
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::emails::dsl::emails;
+
+                let email = emails.find(email_id).first::<Email>(&conn)?;
+                cost::paid(&conn, email, from, to)
+            })
+            .await;
+
+            match res {
+                Ok(paid_report) => HttpResponse::Ok().json(paid_report),
+                _ => HttpResponse::InternalServerError().body("Error calculating paid"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// UPCOMING
+#[derive(Deserialize)]
+struct UpcomingQuery {
+    days: Option<i64>,
+}
+
+#[get("/emails/{email_id}/upcoming")]
+async fn get_email_upcoming(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+    upcoming_query: web::Query<UpcomingQuery>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these charges");
+    }
+
+    let days = upcoming_query.days.unwrap_or(30).clamp(0, 366);
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::emails::dsl::emails;
+
+                let email = emails.find(email_id).first::<Email>(&conn)?;
+                cost::upcoming(&conn, email, days)
+            })
+            .await;
+
+            match res {
+                Ok(upcoming) => HttpResponse::Ok().json(upcoming),
+                _ => HttpResponse::InternalServerError().body("Error calculating upcoming charges"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// HOUSEHOLDS
+// Creates the household with the creating email as its owner
+#[post("/households")]
+async fn post_household(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    household_body: web::Json<HouseholdPostBody>,
+) -> impl Responder {
+    let owner_email_id = household_body.email_id;
+    if !permitted(&pool, &auth_email, is_self(owner_email_id)).await {
+        return HttpResponse::Forbidden().body("Households can only be created for yourself");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let household_body = household_body.into_inner();
+                    let household = Household {
+                        id: None,
+                        created_at: None,
+                        updated_at: None,
+                        ..household_body.household
+                    };
+
+                    {
+                        use crate::schema::households::dsl::households;
+                        diesel::insert_into(households)
+                            .values(&household)
+                            .execute(&conn)?;
+                    }
+                    let new_household_id =
+                        diesel::select(price::last_insert_rowid).get_result::<i32>(&conn)?;
+
+                    {
+                        use crate::schema::household_members::dsl::household_members;
+                        diesel::insert_into(household_members)
+                            .values(&HouseholdMember {
+                                id: None,
+                                created_at: None,
+                                updated_at: None,
+                                household_id: new_household_id,
+                                email_id: household_body.email_id,
+                                role: String::from(household::OWNER),
+                            })
+                            .execute(&conn)?;
+                    }
+
+                    use crate::schema::households::dsl::households;
+                    households.find(new_household_id).first::<Household>(&conn)
+                })
+            })
+            .await;
+
+            match res {
+                Ok(household) => HttpResponse::Created().json(household),
+                _ => HttpResponse::InternalServerError().body("Error saving household"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[get("/emails/{email_id}/households")]
+async fn get_email_households(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these households");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let household_ids = household::household_ids(&conn, email_id)?;
+
+                use crate::schema::households::dsl::*;
+                households
+                    .filter(id.eq_any(household_ids.into_iter().map(Some)))
+                    .load::<Household>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(households_list) => HttpResponse::Ok().json(households_list),
+                _ => HttpResponse::InternalServerError().body("Error getting households"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// The whole household, with each member's share. Members only.
+#[get("/households/{household_id}")]
+async fn get_household(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    household_id: web::Path<i32>,
+) -> impl Responder {
+    let household_id = household_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(household::role_of(conn, household_id, caller_id)?.is_some())
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not a member of this household");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || household::summary(&conn, household_id)).await;
+
+            match res {
+                Ok(summary) => HttpResponse::Ok().json(summary),
+                Err(BlockingError::Error(diesel::result::Error::NotFound)) => {
+                    HttpResponse::NotFound().body("Household not found")
+                }
+                _ => HttpResponse::InternalServerError().body("Error getting household"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Invites an email address, inviting it again only changes the role. Owners only.
+#[post("/households/{household_id}/invites")]
+async fn post_household_invite(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    household_id: web::Path<i32>,
+    invite_body: web::Json<InvitePostBody>,
+) -> impl Responder {
+    let household_id = household_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(household::role_of(conn, household_id, caller_id)?.as_deref() == Some(household::OWNER))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Only household owners can invite");
+    }
+    if !household::is_role(&invite_body.role) {
+        return HttpResponse::BadRequest().body("Role must be owner, editor or viewer");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let invite = HouseholdInvite {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    household_id,
+                    email: invite_body.email.trim().to_lowercase(),
+                    role: invite_body.role.clone(),
+                    invited_by: household::email_id_of(&conn, &auth_email.0)?,
+                };
+
+                use crate::schema::household_invites::dsl;
+                diesel::replace_into(dsl::household_invites)
+                    .values(&invite)
+                    .execute(&conn)?;
+
+                dsl::household_invites
+                    .filter(dsl::household_id.eq(household_id))
+                    .filter(dsl::email.eq(invite.email))
+                    .first::<HouseholdInvite>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(invite) => HttpResponse::Created().json(invite),
+                _ => HttpResponse::InternalServerError().body("Error saving invite"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[get("/households/{household_id}/invites")]
+async fn get_household_invites(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    household_id: web::Path<i32>,
+) -> impl Responder {
+    let household_id = household_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(household::role_of(conn, household_id, caller_id)?.is_some())
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not a member of this household");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::household_invites::dsl;
+                dsl::household_invites
+                    .filter(dsl::household_id.eq(household_id))
+                    .load::<HouseholdInvite>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(invites) => HttpResponse::Ok().json(invites),
+                _ => HttpResponse::InternalServerError().body("Error getting invites"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Pending invites for the signed in email
+#[get("/invites")]
+async fn get_invites(pool: web::Data<DbPool>, auth_email: AuthEmail) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || household::invites_for(&conn, &auth_email.0)).await;
+
+            match res {
+                Ok(invites) => HttpResponse::Ok().json(invites),
+                _ => HttpResponse::InternalServerError().body("Error getting invites"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Only the Google account the invite was sent to can accept it
+#[post("/invites/{invite_id}/accept")]
+async fn post_invite_accept(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    invite_id: web::Path<i32>,
+) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::household_invites::dsl::household_invites;
+
+                let invite = household_invites
+                    .find(invite_id.into_inner())
+                    .first::<HouseholdInvite>(&conn)?;
+
+                if !invite.email.eq_ignore_ascii_case(auth_email.0.trim()) {
+                    return Ok(None);
+                }
+
+                let member = household::accept_invite(&conn, &invite)?;
+                budget::after_change(&conn, vec![member.email_id], "household");
+
+                Ok(Some(member))
+            })
+            .await;
+
+            match res {
+                Ok(Some(member)) => HttpResponse::Created().json(member),
+                Ok(None) => HttpResponse::Forbidden().body("This invite is for another email"),
+                Err(BlockingError::Error(diesel::result::Error::NotFound)) => {
+                    HttpResponse::NotFound().body("Invite not found")
+                }
+                _ => HttpResponse::InternalServerError().body("Error accepting invite"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Declined by the invited email or revoked by a household owner
+#[delete("/invites/{invite_id}")]
+async fn delete_invite(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    invite_id: web::Path<i32>,
+) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::household_invites::dsl::household_invites;
+
+                let invite = household_invites
+                    .find(invite_id.into_inner())
+                    .first::<HouseholdInvite>(&conn)?;
+
+                let is_owner = match household::email_id_of(&conn, &auth_email.0)? {
+                    Some(caller_id) => {
+                        household::role_of(&conn, invite.household_id, caller_id)?.as_deref()
+                            == Some(household::OWNER)
+                    }
+                    None => false,
+                };
+                if !is_owner && !invite.email.eq_ignore_ascii_case(auth_email.0.trim()) {
+                    return Ok(None);
+                }
+
+                diesel::delete(&invite).execute(&conn).map(Some)
+            })
+            .await;
+
+            match res {
+                Ok(Some(_)) => HttpResponse::Ok().body("OK"),
+                Ok(None) => HttpResponse::Forbidden().body("Not allowed to remove this invite"),
+                Err(BlockingError::Error(diesel::result::Error::NotFound)) => {
+                    HttpResponse::NotFound().body("Invite not found")
+                }
+                _ => HttpResponse::InternalServerError().body("Error removing invite"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Owners only, a household always keeps at least one owner
+#[post("/households/{household_id}/members/{email_id}/role")]
+async fn post_household_member_role(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    path: web::Path<(i32, i32)>,
+    role_body: web::Json<RolePostBody>,
+) -> impl Responder {
+    let (household_id, email_id) = path.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(household::role_of(conn, household_id, caller_id)?.as_deref() == Some(household::OWNER))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Only household owners can change roles");
+    }
+    if !household::is_role(&role_body.role) {
+        return HttpResponse::BadRequest().body("Role must be owner, editor or viewer");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                if role_body.role != household::OWNER
+                    && household::is_last_owner(&conn, household_id, email_id)?
+                {
+                    return Ok(None);
+                }
+
+                use crate::schema::household_members::dsl;
+                diesel::update(
+                    dsl::household_members
+                        .filter(dsl::household_id.eq(household_id))
+                        .filter(dsl::email_id.eq(email_id)),
+                )
+                .set(dsl::role.eq(&role_body.role))
+                .execute(&conn)
+                .map(Some)
+            })
+            .await;
+
+            match res {
+                Ok(Some(_)) => HttpResponse::Ok().body("OK"),
+                Ok(None) => HttpResponse::BadRequest().body("A household needs an owner"),
+                _ => HttpResponse::InternalServerError().body("Error changing role"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Owners can remove anyone, other members only themselves
+#[delete("/households/{household_id}/members/{email_id}")]
+async fn delete_household_member(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    path: web::Path<(i32, i32)>,
+) -> impl Responder {
+    let (household_id, email_id) = path.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(caller_id == email_id
+            || household::role_of(conn, household_id, caller_id)?.as_deref()
+                == Some(household::OWNER))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to remove this member");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let removed = conn.transaction(|| {
+                    if household::is_last_owner(&conn, household_id, email_id)? {
+                        return Ok(None);
+                    }
+
+                    household::remove_member(&conn, household_id, email_id).map(Some)
+                })?;
+
+                if removed.is_some() {
+                    let mut email_ids = vec![email_id];
+                    email_ids.extend(
+                        household::members(&conn, vec![household_id])?
+                            .into_iter()
+                            .map(|member| member.email_id),
+                    );
+                    budget::after_change(&conn, email_ids, "household");
+                }
+
+                Ok::<_, diesel::result::Error>(removed)
+            })
+            .await;
+
+            match res {
+                Ok(Some(_)) => HttpResponse::Ok().body("OK"),
+                Ok(None) => HttpResponse::BadRequest().body("A household needs an owner"),
+                _ => HttpResponse::InternalServerError().body("Error removing member"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[get("/subscriptions/{subscription_id}/shares")]
+async fn get_subscription_shares(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    subscription_id: web::Path<i32>,
+) -> impl Responder {
+    let subscription_id = subscription_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        household::can_view_subscription(conn, subscription_id, caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to see this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || household::shares(&conn, vec![subscription_id])).await;
+
+            match res {
+                Ok(shares) => HttpResponse::Ok().json(shares),
+                _ => HttpResponse::InternalServerError().body("Error getting shares"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Replaces how the subscription is split, see household::share_of.
+// Needs edit rights on both the subscription and the household it's shared with.
+#[post("/subscriptions/{subscription_id}/shares")]
+async fn post_subscription_shares(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    subscription_id: web::Path<i32>,
+    if_match: IfMatch,
+    shares_body: web::Json<household::SharesBody>,
+) -> impl Responder {
+    let subscription_id = subscription_id.into_inner();
+    let target_household_id = shares_body.household_id;
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(
+            household::can_edit_subscription(conn, subscription_id, caller_id)?
+                && match target_household_id {
+                    Some(household_id) => {
+                        household::can_edit_household(conn, household_id, caller_id)?
+                    }
+                    None => true,
+                },
+        )
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to share this subscription");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let subscription = {
+                    use crate::schema::subscriptions::dsl::subscriptions;
+                    subscriptions
+                        .find(subscription_id)
+                        .first::<Subscription>(&conn)
+                        .map_err(|e| e.to_string())?
+                };
+
+                // Sharing moves the subscription between households, so it's versioned too
+                if !if_match.matches(subscription.version) {
+                    return Ok(Outcome::Conflict(subscription));
+                }
+
+                let shares =
+                    household::set_shares(&conn, subscription_id, shares_body.into_inner())?;
+                budget::after_change(&conn, vec![subscription.email_id], "household");
+                events::saved_subscription_changed(
+                    &conn,
+                    webhook::SUBSCRIPTION_UPDATED,
+                    subscription_id,
+                );
+
+                Ok::<_, String>(Outcome::Saved(shares))
+            })
+            .await;
+
+            match res {
+                Ok(Outcome::Saved(shares)) => HttpResponse::Ok().json(shares),
+                Ok(Outcome::Conflict(current)) => version::conflict(current.version, &current),
+                Err(BlockingError::Error(e)) => HttpResponse::BadRequest().body(e),
+                _ => HttpResponse::InternalServerError().body("Error saving shares"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// BUDGETS
+#[get("/emails/{email_id}/budgets")]
+async fn get_email_budgets(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these budgets");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || budget::budgets(&conn, email_id)).await;
+
+            match res {
+                Ok(budgets) => HttpResponse::Ok().json(budgets),
+                _ => HttpResponse::InternalServerError().body("Error getting budgets"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Sets the monthly limit, overall when category_id is null
+#[post("/budgets")]
+async fn post_budget(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    budget_body: web::Json<Budget>,
+) -> impl Responder {
+    let saved_budget = budget_body.clone();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(saved_budget.email_id == caller_id
+            && match saved_budget.category_id {
+                Some(category_id) => can_edit_category(conn, Some(category_id), caller_id)?,
+                None => true,
+            })
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Budgets can only be set for yourself");
+    }
+    if budget_body.monthly_limit < 0.0 {
+        return HttpResponse::BadRequest().body("monthly_limit must not be negative");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let saved = conn.transaction(|| {
+                    let before = budget::budgets(&conn, budget_body.email_id)?
+                        .into_iter()
+                        .find(|existing| existing.category_id == budget_body.category_id);
+
+                    let saved = budget::save_budget(&conn, &budget_body)?;
+                    audit::record(
+                        &conn,
+                        &actor,
+                        audit::BUDGET,
+                        Some(saved.email_id),
+                        before.as_ref(),
+                        Some(&saved),
+                    )?;
+
+                    Ok::<_, diesel::result::Error>(saved)
+                })?;
+                budget::after_change(&conn, vec![saved.email_id], "budget");
+
+                Ok::<_, diesel::result::Error>(saved)
+            })
+            .await;
+
+            match res {
+                Ok(saved) => HttpResponse::Created().json(saved),
+                _ => HttpResponse::InternalServerError().body("Error saving budget"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/budgets/{budget_id}")]
+async fn delete_budget(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    budget_id: web::Path<i32>,
+) -> impl Responder {
+    let budget_id = budget_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        use crate::schema::budgets::dsl::budgets;
+        let stored = budgets.find(budget_id).first::<Budget>(conn).optional()?;
+
+        Ok(stored
+            .map(|stored| stored.email_id == caller_id)
+            .unwrap_or(true))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to remove this budget");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::budgets::dsl::budgets;
+
+                conn.transaction(|| {
+                    let deleted = budgets.find(budget_id).first::<Budget>(&conn).optional()?;
+                    let removed = budget::delete_budget(&conn, budget_id)?;
+
+                    if let Some(deleted) = deleted {
+                        audit::record(
+                            &conn,
+                            &actor,
+                            audit::BUDGET,
+                            Some(deleted.email_id),
+                            Some(&deleted),
+                            None,
+                        )?;
+                    }
+
+                    Ok::<_, diesel::result::Error>(removed)
+                })
+            })
+            .await;
+
+            match res {
+                Ok(_) => HttpResponse::Ok().body("OK"),
+                _ => HttpResponse::InternalServerError().body("Error deleting budget"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Spending against each budget right now
+#[get("/emails/{email_id}/budgets/check")]
+async fn get_email_budgets_check(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these budgets");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::emails::dsl::emails;
+
+                let email = emails.find(email_id).first::<Email>(&conn)?;
+                budget::check(&conn, email)
+            })
+            .await;
+
+            match res {
+                Ok(statuses) => HttpResponse::Ok().json(statuses),
+                _ => HttpResponse::InternalServerError().body("Error checking budgets"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Latest first
+#[get("/emails/{email_id}/budget-alerts")]
+async fn get_email_budget_alerts(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these budget alerts");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::budget_alerts::dsl;
+                dsl::budget_alerts
+                    .filter(dsl::email_id.eq(email_id))
+                    .order(dsl::id.desc())
+                    .load::<BudgetAlert>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(alerts) => HttpResponse::Ok().json(alerts),
+                _ => HttpResponse::InternalServerError().body("Error getting budget alerts"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// WEBHOOKS
+// Whether the stored webhook, if there is one, belongs to the email
+fn can_edit_webhook(
+    conn: &SqliteConnection,
+    webhook_id: Option<i32>,
+    caller_id: i32,
+) -> QueryResult<bool> {
+    match webhook_id {
+        Some(webhook_id) => {
+            use crate::schema::webhooks::dsl::webhooks;
+            let stored = webhooks
+                .find(webhook_id)
+                .first::<Webhook>(conn)
+                .optional()?;
+
+            Ok(stored
+                .map(|stored| stored.email_id == caller_id)
+                .unwrap_or(true))
+        }
+        None => Ok(true),
+    }
+}
+
+// Includes the secrets, so only for the email itself
+#[get("/emails/{email_id}/webhooks")]
+async fn get_email_webhooks(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these webhooks");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::webhooks::dsl;
+                dsl::webhooks
+                    .filter(dsl::email_id.eq(email_id))
+                    .load::<Webhook>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+                _ => HttpResponse::InternalServerError().body("Error getting webhooks"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[post("/webhooks")]
+async fn post_webhook(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    webhook_body: web::Json<Webhook>,
+) -> impl Responder {
+    let saved_webhook = webhook_body.clone();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        Ok(saved_webhook.email_id == caller_id
+            && can_edit_webhook(conn, saved_webhook.id, caller_id)?)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Webhooks can only be set for yourself");
+    }
+    if let Err(e) = webhook::check_url(&webhook_body.url).await {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if webhook_body.secret.len() < 16 {
+        return HttpResponse::BadRequest().body("secret must be at least 16 characters");
+    }
+    let unknown = webhook::unknown_event_types(&webhook_body.event_types);
+    if !unknown.is_empty() {
+        return HttpResponse::BadRequest().body(format!(
+            "Unknown event types: {}. Known: {}",
+            unknown.join(", "),
+            webhook::EVENT_TYPES.join(", ")
+        ));
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let webhook_body = webhook_body.into_inner();
+                webhook_body.save(&conn)?;
+
+                let saved_id = match webhook_body.id {
+                    Some(saved_id) => saved_id,
+                    None => diesel::select(price::last_insert_rowid).get_result::<i32>(&conn)?,
+                };
+
+                use crate::schema::webhooks::dsl::webhooks;
+                webhooks.find(saved_id).first::<Webhook>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(saved) => HttpResponse::Created().json(saved),
+                _ => HttpResponse::InternalServerError().body("Error saving webhook"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/webhooks/{webhook_id}")]
+async fn delete_webhook(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    webhook_id: web::Path<i32>,
+) -> impl Responder {
+    let webhook_id = webhook_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        can_edit_webhook(conn, Some(webhook_id), caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to remove this webhook");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res =
+                web::block(move || conn.transaction(|| webhook::delete_webhook(&conn, webhook_id)))
+                    .await;
+
+            match res {
+                Ok(_) => HttpResponse::Ok().body("OK"),
+                _ => HttpResponse::InternalServerError().body("Error deleting webhook"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// The delivery log, latest first
+#[get("/webhooks/{webhook_id}/deliveries")]
+async fn get_webhook_deliveries(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    webhook_id: web::Path<i32>,
+) -> impl Responder {
+    let webhook_id = webhook_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        can_edit_webhook(conn, Some(webhook_id), caller_id)
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to see this webhook");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::webhook_deliveries::dsl;
+                dsl::webhook_deliveries
+                    .filter(dsl::webhook_id.eq(webhook_id))
+                    .order(dsl::id.desc())
+                    .limit(200)
+                    .load::<WebhookDelivery>(&conn)
+            })
+            .await;
+
+            match res {
+                Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+                _ => HttpResponse::InternalServerError().body("Error getting deliveries"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// API KEYS
+#[get("/emails/{email_id}/api-keys")]
+async fn get_email_api_keys(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these API keys");
+    }
+
+    match pool.get() {
+        Ok(conn) => match web::block(move || api_key::api_keys_of(&conn, email_id)).await {
+            Ok(api_keys) => HttpResponse::Ok().json(api_keys),
+            _ => HttpResponse::InternalServerError().body("Error getting API keys"),
+        },
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// The key itself is only in this response
+#[post("/api-keys")]
+async fn post_api_key(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    req: HttpRequest,
+    api_key_body: web::Json<api_key::ApiKeyBody>,
+) -> impl Responder {
+    // Like logins, so an expiring key can't make one that lives longer
+    if auth::id_token(req.headers(), req.path(), req.query_string())
+        .is_some_and(|id_token| api_key::is_api_key(&id_token))
+    {
+        return HttpResponse::Forbidden().body("API keys can't create API keys");
+    }
+    if api_key_body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("name is required");
+    }
+    if api_key_body
+        .expires_in_days
+        .is_some_and(|days| !(1..=api_key::MAX_EXPIRES_IN_DAYS).contains(&days))
+    {
+        return HttpResponse::BadRequest().body(format!(
+            "expires_in_days must be between 1 and {}",
+            api_key::MAX_EXPIRES_IN_DAYS
+        ));
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(
+                move || match household::email_id_of(&conn, &auth_email.0)? {
+                    Some(caller_id) => {
+                        api_key::create(&conn, caller_id, api_key_body.into_inner()).map(Some)
+                    }
+                    None => Ok(None),
+                },
+            )
+            .await;
+
+            match res {
+                Ok(Some(created)) => HttpResponse::Created().json(created),
+                Ok(None) => HttpResponse::Forbidden().body("Unknown email"),
+                _ => HttpResponse::InternalServerError().body("Error creating API key"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/api-keys/{api_key_id}")]
+async fn delete_api_key(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    api_key_id: web::Path<i32>,
+) -> impl Responder {
+    let api_key_id = api_key_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        use crate::schema::api_keys::dsl::*;
+        api_keys
+            .find(api_key_id)
+            .select(email_id)
+            .first::<i32>(conn)
+            .optional()
+            .map(|owner_id| owner_id == Some(caller_id))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to revoke this API key");
+    }
+
+    match pool.get() {
+        Ok(conn) => match web::block(move || api_key::delete(&conn, api_key_id)).await {
+            Ok(_) => HttpResponse::Ok().body("OK"),
+            _ => HttpResponse::InternalServerError().body("Error revoking API key"),
+        },
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Changes to the email's data, newest first. Filter with entity, action, actor, from and to.
+#[get("/emails/{email_id}/audit")]
+async fn get_email_audit(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+    audit_query: web::Query<audit::AuditQuery>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see this audit log");
+    }
+
+    match pool.get() {
+        Ok(conn) => match web::block(move || audit::logs_of(&conn, email_id, &audit_query)).await {
+            Ok(logs) => HttpResponse::Ok().json(logs),
+            _ => HttpResponse::InternalServerError().body("Error getting audit log"),
+        },
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Live changes
+// EventSource can't set headers, so the id token may also come as ?access_token=
+#[get("/events")]
+async fn get_events(pool: web::Data<DbPool>, auth_email: AuthEmail) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || household::email_id_of(&conn, &auth_email.0)).await;
+
+            match res {
+                Ok(Some(email_id)) => HttpResponse::Ok()
+                    .content_type("text/event-stream")
+                    .header("cache-control", "no-cache")
+                    // Compressing would buffer the stream
+                    .encoding(ContentEncoding::Identity)
+                    .streaming(events::stream_for(email_id)),
+                Ok(None) => HttpResponse::Forbidden().finish(),
+                _ => HttpResponse::InternalServerError().body("Error getting email"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Currencies
+#[get("/currencies")]
+async fn get_currencies(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    auth_email: Option<AuthEmail>,
+) -> impl Responder {
+    // println!("Currency headers: {:?}", req.headers());
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                use crate::schema::currencies::dsl::*;
+
+                let currencies_list = currencies.load::<Currencie>(&conn)?;
+
+                // Signed in emails see their own rates
+                match auth_email {
+                    Some(auth_email) => {
+                        let owner_id = household::email_id_of(&conn, &auth_email.0)?;
+                        currency::with_overrides(&conn, currencies_list, owner_id)
+                    }
+                    None => Ok(currencies_list),
+                }
+            })
+            .await;
+            match res {
+                Ok(currencies) => HttpResponse::Ok().json(currencies),
+                _ => HttpResponse::InternalServerError().body("Error fetching currencies"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// A currency the provider doesn't have, admins only
+#[post("/currencies")]
+async fn post_currency(
+    pool: web::Data<DbPool>,
+    actor: audit::Actor,
+    currency_body: web::Json<currency::CustomCurrencyBody>,
+) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            match web::block(move || currency::create_custom(&conn, &actor, &currency_body)).await {
+                Ok(created) => HttpResponse::Created().json(created),
+                Err(BlockingError::Error(e)) => HttpResponse::BadRequest().body(e),
+                _ => HttpResponse::InternalServerError().body("Error adding currency"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// Pins the rate for everyone, admins only. A null rate unpins it.
+#[post("/currencies/{currencie_id}/rate")]
+async fn post_currency_rate(
+    pool: web::Data<DbPool>,
+    actor: audit::Actor,
+    currencie_id: web::Path<i32>,
+    pin_body: web::Json<currency::PinBody>,
+) -> impl Responder {
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let saved = currency::pin(&conn, &actor, currencie_id.into_inner(), pin_body.rate)?;
+                if let Err(e) = budget::run_all_hooks(&conn, "rates") {
+                    println!("Error running budget hooks: {:?}", e);
+                }
+
+                Ok::<_, String>(saved)
+            })
+            .await;
+
+            match res {
+                Ok(saved) => {
+                    events::publish(None, events::RATES_REFRESHED, serde_json::Value::Null);
+                    HttpResponse::Ok().json(saved)
+                }
+                Err(BlockingError::Error(e)) => HttpResponse::BadRequest().body(e),
+                _ => HttpResponse::InternalServerError().body("Error setting rate"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// When rates were last refreshed and why the last try failed, admins only
+#[get("/rates/status")]
+async fn get_rates_status() -> impl Responder {
+    HttpResponse::Ok().json(rates::status())
+}
+
+#[get("/emails/{email_id}/rate-overrides")]
+async fn get_email_rate_overrides(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    email_id: web::Path<i32>,
+) -> impl Responder {
+    let email_id = email_id.into_inner();
+    if !permitted(&pool, &auth_email, is_self(email_id)).await {
+        return HttpResponse::Forbidden().body("Not allowed to see these rates");
+    }
+
+    match pool.get() {
+        Ok(conn) => match web::block(move || currency::overrides_of(&conn, email_id)).await {
+            Ok(overrides) => HttpResponse::Ok().json(overrides),
+            _ => HttpResponse::InternalServerError().body("Error getting rates"),
+        },
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+// The email's own rate for a currency, used in its costs instead of the shared one
+#[post("/rate-overrides")]
+async fn post_rate_override(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    override_body: web::Json<RateOverride>,
+) -> impl Responder {
+    let owner_id = override_body.email_id;
+    if !permitted(&pool, &auth_email, is_self(owner_id)).await {
+        return HttpResponse::Forbidden().body("Rates can only be set for yourself");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let res = web::block(move || {
+                let saved = currency::save_override(&conn, &actor, &override_body)?;
+                budget::after_change(&conn, vec![saved.email_id], "rates");
+
+                Ok::<_, String>(saved)
+            })
+            .await;
+
+            match res {
+                Ok(saved) => {
+                    events::publish(
+                        Some(vec![saved.email_id]),
+                        events::RATES_REFRESHED,
+                        serde_json::Value::Null,
+                    );
+                    HttpResponse::Created().json(saved)
+                }
+                Err(BlockingError::Error(e)) => HttpResponse::BadRequest().body(e),
+                _ => HttpResponse::InternalServerError().body("Error setting rate"),
+            }
+        }
+        _ => HttpResponse::InternalServerError().body("Error getting pool"),
+    }
+}
+
+#[delete("/rate-overrides/{rate_override_id}")]
+async fn delete_rate_override(
+    pool: web::Data<DbPool>,
+    auth_email: AuthEmail,
+    actor: audit::Actor,
+    rate_override_id: web::Path<i32>,
+) -> impl Responder {
+    let rate_override_id = rate_override_id.into_inner();
+    if !permitted(&pool, &auth_email, move |conn, caller_id| {
+        use crate::schema::rate_overrides::dsl::rate_overrides;
+        let stored = rate_overrides
+            .find(rate_override_id)
+            .first::<RateOverride>(conn)
+            .optional()?;
+
+        Ok(stored
+            .map(|stored| stored.email_id == caller_id)
+            .unwrap_or(true))
+    })
+    .await
+    {
+        return HttpResponse::Forbidden().body("Not allowed to remove this rate");
+    }
+
+    match pool.get() {
+        Ok(conn) => {
+            let caller = auth_email.0.clone();
+            let res = web::block(move || {
+                let removed = currency::delete_override(&conn, &actor, rate_override_id)?;
+                if let Some(caller_id) = household::email_id_of(&conn, &caller)? {
+                    budget::after_change(&conn, vec![caller_id], "rates");
+                }
+
+                Ok::<_, diesel::result::Error>(removed)
+            })
+            .await;
+
+            match res {
+                Ok(_) => HttpResponse::Ok().body("OK"),
+                _ => HttpResponse::InternalServerError().body("Error removing rate"),
             }
         }
         _ => HttpResponse::InternalServerError().body("Error getting pool"),
     }
 }
 
-// Intervals
 #[get("/intervals")]
 async fn get_intervals(pool: web::Data<DbPool>) -> impl Responder {
     match pool.get() {
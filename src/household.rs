@@ -0,0 +1,737 @@
+use chrono::Utc;
+use diesel::prelude::*;
+
+use crate::cost::{self, CostTotal};
+use crate::model::{
+    Currencie, Email, Household, HouseholdInvite, HouseholdMember, Interval, Subscription,
+    SubscriptionShare,
+};
+use crate::{lifecycle, price};
+
+pub const OWNER: &str = "owner";
+pub const EDITOR: &str = "editor";
+pub const VIEWER: &str = "viewer";
+
+pub fn is_role(role: &str) -> bool {
+    role == OWNER || role == EDITOR || role == VIEWER
+}
+
+// The part of a subscription's cost carried by an email, between 0 and 1.
+// Unshared subscriptions are carried entirely by their owner. Shared ones without explicit
+// shares are split equally between the household members. Otherwise fixed shares come first,
+// ratios split what's left, and the owner carries whatever nobody else does.
+pub fn share_of(
+    subscription: &Subscription,
+    members: &[HouseholdMember],
+    shares: &[SubscriptionShare],
+    email_id: i32,
+) -> f32 {
+    let is_owner = subscription.email_id == email_id;
+
+    let household_id = match subscription.household_id {
+        Some(household_id) => household_id,
+        None => return if is_owner { 1.0 } else { 0.0 },
+    };
+
+    let subscription_shares: Vec<&SubscriptionShare> = shares
+        .iter()
+        .filter(|share| Some(share.subscription_id) == subscription.id)
+        .collect();
+
+    if subscription_shares.is_empty() {
+        let mut parties: Vec<i32> = members
+            .iter()
+            .filter(|member| member.household_id == household_id)
+            .map(|member| member.email_id)
+            .collect();
+        if !parties.contains(&subscription.email_id) {
+            parties.push(subscription.email_id);
+        }
+
+        return if parties.contains(&email_id) {
+            1.0 / parties.len() as f32
+        } else {
+            0.0
+        };
+    }
+
+    let fixed_fraction = |share: &SubscriptionShare| match share.fixed_amount {
+        Some(fixed_amount) if subscription.cost > 0.0 => {
+            (fixed_amount / subscription.cost).max(0.0)
+        }
+        _ => 0.0,
+    };
+
+    // Fixed shares adding up to more than the cost are scaled down to fit
+    let fixed_total: f32 = subscription_shares
+        .iter()
+        .map(|share| fixed_fraction(share))
+        .sum();
+    let fixed_scale = if fixed_total > 1.0 {
+        1.0 / fixed_total
+    } else {
+        1.0
+    };
+    let remaining = 1.0 - fixed_total.min(1.0);
+
+    let ratio_total: f32 = subscription_shares
+        .iter()
+        .filter(|share| share.fixed_amount.is_none())
+        .filter_map(|share| share.ratio)
+        .map(|ratio| ratio.max(0.0))
+        .sum();
+
+    let share_fraction = |share: &SubscriptionShare| match (share.fixed_amount, share.ratio) {
+        (Some(_), _) => fixed_fraction(share) * fixed_scale,
+        (None, Some(ratio)) if ratio_total > 0.0 => ratio.max(0.0) / ratio_total * remaining,
+        _ => 0.0,
+    };
+
+    let own_share: f32 = subscription_shares
+        .iter()
+        .filter(|share| share.email_id == email_id)
+        .map(|share| share_fraction(share))
+        .sum();
+
+    if is_owner {
+        let allocated: f32 = subscription_shares
+            .iter()
+            .map(|share| share_fraction(share))
+            .sum();
+
+        own_share + (1.0 - allocated).max(0.0)
+    } else {
+        own_share
+    }
+}
+
+// Households the email belongs to
+pub fn household_ids(conn: &SqliteConnection, email_id: i32) -> QueryResult<Vec<i32>> {
+    use crate::schema::household_members::dsl;
+
+    dsl::household_members
+        .filter(dsl::email_id.eq(email_id))
+        .select(dsl::household_id)
+        .load::<i32>(conn)
+}
+
+pub fn members(
+    conn: &SqliteConnection,
+    household_ids: Vec<i32>,
+) -> QueryResult<Vec<HouseholdMember>> {
+    use crate::schema::household_members::dsl;
+
+    dsl::household_members
+        .filter(dsl::household_id.eq_any(household_ids))
+        .load::<HouseholdMember>(conn)
+}
+
+pub fn shares(
+    conn: &SqliteConnection,
+    subscription_ids: Vec<i32>,
+) -> QueryResult<Vec<SubscriptionShare>> {
+    use crate::schema::subscription_shares::dsl;
+
+    dsl::subscription_shares
+        .filter(dsl::subscription_id.eq_any(subscription_ids))
+        .load::<SubscriptionShare>(conn)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareBody {
+    pub email_id: i32,
+    pub ratio: Option<f32>,
+    pub fixed_amount: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharesBody {
+    pub household_id: Option<i32>,
+    pub shares: Vec<ShareBody>,
+}
+
+// Shares the subscription with a household, replacing its previous shares.
+// No household_id makes it private to its owner again.
+pub fn set_shares(
+    conn: &SqliteConnection,
+    subscription_id: i32,
+    shares_body: SharesBody,
+) -> Result<Vec<SubscriptionShare>, String> {
+    let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+        if let Some(household_id) = shares_body.household_id {
+            let household_members = members(conn, vec![household_id])?;
+            let subscription = {
+                use crate::schema::subscriptions::dsl::subscriptions;
+                subscriptions
+                    .find(subscription_id)
+                    .first::<Subscription>(conn)?
+            };
+
+            // Only members can share into the household, and only with other members
+            let is_member = |member_email_id: i32| {
+                household_members
+                    .iter()
+                    .any(|member| member.email_id == member_email_id)
+            };
+
+            let valid = is_member(subscription.email_id)
+                && shares_body.shares.iter().all(|share| {
+                    is_member(share.email_id)
+                        && share.ratio.or(share.fixed_amount).is_some()
+                        && share.ratio.unwrap_or(0.0) >= 0.0
+                        && share.fixed_amount.unwrap_or(0.0) >= 0.0
+                });
+            if !valid {
+                return Ok(None);
+            }
+        }
+
+        unshare(conn, subscription_id)?;
+
+        let household_id = match shares_body.household_id {
+            Some(household_id) => household_id,
+            None => return Ok(Some(vec![])),
+        };
+
+        {
+            use crate::schema::subscriptions::dsl;
+            diesel::update(dsl::subscriptions.find(subscription_id))
+                .set(dsl::household_id.eq(household_id))
+                .execute(conn)?;
+        }
+
+        use crate::schema::subscription_shares::dsl::subscription_shares;
+
+        let new_shares: Vec<SubscriptionShare> = shares_body
+            .shares
+            .iter()
+            .map(|share| SubscriptionShare {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                subscription_id,
+                email_id: share.email_id,
+                ratio: share.ratio,
+                fixed_amount: share.fixed_amount,
+            })
+            .collect();
+
+        diesel::insert_or_ignore_into(subscription_shares)
+            .values(&new_shares)
+            .execute(conn)?;
+
+        Ok(Some(shares(conn, vec![subscription_id])?))
+    });
+
+    match result {
+        Ok(Some(saved_shares)) => Ok(saved_shares),
+        Ok(None) => Err(String::from(
+            "The owner and everyone sharing must be household members, each share with a \
+             ratio or fixed amount that isn't negative",
+        )),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberTotal {
+    pub email_id: i32,
+    pub email: String,
+    pub total: CostTotal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedSubscription {
+    pub subscription_id: Option<i32>,
+    pub name: String,
+    pub email_id: i32,
+    pub monthly: f32,
+    pub shares: Vec<MemberShare>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberShare {
+    pub email_id: i32,
+    pub share: f32,
+    pub monthly: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HouseholdSummary {
+    pub household: Household,
+    pub currencie_id: Option<i32>,
+    pub total: CostTotal,
+    pub members: Vec<MemberTotal>,
+    pub subscriptions: Vec<SharedSubscription>,
+}
+
+// The whole household: every shared subscription in full, and what each member carries
+pub fn summary(conn: &SqliteConnection, household_id: i32) -> QueryResult<HouseholdSummary> {
+    let household = {
+        use crate::schema::households::dsl::households;
+        households.find(household_id).first::<Household>(conn)?
+    };
+    let household_members = members(conn, vec![household_id])?;
+    let member_emails = {
+        use crate::schema::emails::dsl::*;
+        let email_ids: Vec<i32> = household_members
+            .iter()
+            .map(|member| member.email_id)
+            .collect();
+        emails.filter(id.eq_any(email_ids)).load::<Email>(conn)?
+    };
+    let subscriptions_list = {
+        use crate::schema::subscriptions::dsl::*;
+        subscriptions
+            .filter(household_id.eq(household.id))
+            .load::<Subscription>(conn)?
+    };
+    let subscription_shares = shares(
+        conn,
+        subscriptions_list
+            .iter()
+            .filter_map(|subscription| subscription.id)
+            .collect(),
+    )?;
+    let intervals_list = {
+        use crate::schema::intervals::dsl::*;
+        intervals.load::<Interval>(conn)?
+    };
+    let currencies_list = {
+        use crate::schema::currencies::dsl::*;
+        currencies.load::<Currencie>(conn)?
+    };
+
+    let now = Utc::now().naive_utc();
+    let mut total = CostTotal::default();
+    let mut member_totals: Vec<MemberTotal> = member_emails
+        .iter()
+        .filter_map(|email| {
+            Some(MemberTotal {
+                email_id: email.id?,
+                email: email.email.clone(),
+                total: CostTotal::default(),
+            })
+        })
+        .collect();
+
+    let shared_subscriptions = subscriptions_list
+        .iter()
+        .filter(|subscription| lifecycle::is_active_at(subscription, now))
+        .map(|subscription| {
+            let monthly = cost::monthly_cost(
+                subscription,
+                &intervals_list,
+                &currencies_list,
+                household.currencie_id,
+                now,
+            );
+            total.add(monthly);
+
+            let member_shares = member_totals
+                .iter_mut()
+                .map(|member_total| {
+                    let share = share_of(
+                        subscription,
+                        &household_members,
+                        &subscription_shares,
+                        member_total.email_id,
+                    );
+                    member_total.total.add(monthly * share);
+
+                    MemberShare {
+                        email_id: member_total.email_id,
+                        share,
+                        monthly: monthly * share,
+                    }
+                })
+                .collect();
+
+            SharedSubscription {
+                subscription_id: subscription.id,
+                name: subscription.name.clone(),
+                email_id: subscription.email_id,
+                monthly,
+                shares: member_shares,
+            }
+        })
+        .collect();
+
+    let shown_in = cost::find_currency(&currencies_list, household.currencie_id);
+
+    Ok(HouseholdSummary {
+        currencie_id: household.currencie_id,
+        household,
+        total: total.formatted(shown_in),
+        members: member_totals
+            .into_iter()
+            .map(|member| MemberTotal {
+                total: member.total.formatted(shown_in),
+                ..member
+            })
+            .collect(),
+        subscriptions: shared_subscriptions,
+    })
+}
+
+// Makes the subscription private to its owner again
+pub fn unshare(conn: &SqliteConnection, subscription_id: i32) -> QueryResult<usize> {
+    {
+        use crate::schema::subscription_shares::dsl;
+        diesel::delete(dsl::subscription_shares.filter(dsl::subscription_id.eq(subscription_id)))
+            .execute(conn)?;
+    }
+
+    use crate::schema::subscriptions::dsl;
+    diesel::update(dsl::subscriptions.find(subscription_id))
+        .set(dsl::household_id.eq(None::<i32>))
+        .execute(conn)
+}
+
+// Takes the email out of the household. Its subscriptions stop being shared there and
+// its shares of other members' subscriptions go back to their owners.
+pub fn remove_member(
+    conn: &SqliteConnection,
+    household_id: i32,
+    email_id: i32,
+) -> QueryResult<usize> {
+    let household_subscriptions = {
+        use crate::schema::subscriptions::dsl;
+        dsl::subscriptions
+            .filter(dsl::household_id.eq(household_id))
+            .load::<Subscription>(conn)?
+    };
+
+    for subscription in &household_subscriptions {
+        let subscription_id = match subscription.id {
+            Some(subscription_id) => subscription_id,
+            None => continue,
+        };
+
+        if subscription.email_id == email_id {
+            unshare(conn, subscription_id)?;
+        } else {
+            use crate::schema::subscription_shares::dsl;
+            diesel::delete(
+                dsl::subscription_shares
+                    .filter(dsl::subscription_id.eq(subscription_id))
+                    .filter(dsl::email_id.eq(email_id)),
+            )
+            .execute(conn)?;
+        }
+    }
+
+    use crate::schema::household_members::dsl;
+    diesel::delete(
+        dsl::household_members
+            .filter(dsl::household_id.eq(household_id))
+            .filter(dsl::email_id.eq(email_id)),
+    )
+    .execute(conn)
+}
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+// The id of an email address, matched case-insensitively as Google reports it
+pub fn email_id_of(conn: &SqliteConnection, address: &str) -> QueryResult<Option<i32>> {
+    use crate::schema::emails::dsl::*;
+
+    emails
+        .filter(lower(email).eq(address.trim().to_lowercase()))
+        .select(id)
+        .first::<Option<i32>>(conn)
+        .optional()
+        .map(Option::flatten)
+}
+
+pub fn invites_for(conn: &SqliteConnection, address: &str) -> QueryResult<Vec<HouseholdInvite>> {
+    use crate::schema::household_invites::dsl::*;
+
+    household_invites
+        .filter(lower(email).eq(address.trim().to_lowercase()))
+        .load::<HouseholdInvite>(conn)
+}
+
+pub fn role_of(
+    conn: &SqliteConnection,
+    household_id: i32,
+    email_id: i32,
+) -> QueryResult<Option<String>> {
+    use crate::schema::household_members::dsl;
+
+    dsl::household_members
+        .filter(dsl::household_id.eq(household_id))
+        .filter(dsl::email_id.eq(email_id))
+        .select(dsl::role)
+        .first::<String>(conn)
+        .optional()
+}
+
+pub fn can_edit_household(
+    conn: &SqliteConnection,
+    household_id: i32,
+    email_id: i32,
+) -> QueryResult<bool> {
+    Ok(matches!(
+        role_of(conn, household_id, email_id)?.as_deref(),
+        Some(OWNER) | Some(EDITOR)
+    ))
+}
+
+// Owners can edit their own subscriptions, household owners and editors those shared there
+pub fn can_edit_subscription(
+    conn: &SqliteConnection,
+    subscription_id: i32,
+    email_id: i32,
+) -> QueryResult<bool> {
+    use crate::schema::subscriptions::dsl::subscriptions;
+
+    let stored = match subscriptions
+        .find(subscription_id)
+        .first::<Subscription>(conn)
+        .optional()?
+    {
+        Some(stored) => stored,
+        None => return Ok(false),
+    };
+
+    if stored.email_id == email_id {
+        return Ok(true);
+    }
+
+    match stored.household_id {
+        Some(household_id) => can_edit_household(conn, household_id, email_id),
+        None => Ok(false),
+    }
+}
+
+// Owners can see their own subscriptions, every member of the household one is shared with
+pub fn can_view_subscription(
+    conn: &SqliteConnection,
+    subscription_id: i32,
+    email_id: i32,
+) -> QueryResult<bool> {
+    use crate::schema::subscriptions::dsl::subscriptions;
+
+    match subscriptions
+        .find(subscription_id)
+        .first::<Subscription>(conn)
+        .optional()?
+    {
+        Some(stored) if stored.email_id == email_id => Ok(true),
+        Some(Subscription {
+            household_id: Some(household_id),
+            ..
+        }) => Ok(role_of(conn, household_id, email_id)?.is_some()),
+        _ => Ok(false),
+    }
+}
+
+// Like can_edit_subscription, also for new subscriptions, which must be the email's own and
+// can't start out shared. A save can't hand the subscription over to another email.
+pub fn can_save_subscription(
+    conn: &SqliteConnection,
+    subscription: &Subscription,
+    email_id: i32,
+) -> QueryResult<bool> {
+    let stored = match subscription.id {
+        Some(subscription_id) => {
+            use crate::schema::subscriptions::dsl::subscriptions;
+            subscriptions
+                .find(subscription_id)
+                .first::<Subscription>(conn)
+                .optional()?
+        }
+        None => None,
+    };
+
+    match stored {
+        Some(stored) => Ok(stored.email_id == subscription.email_id
+            && can_edit_subscription(conn, subscription.id.unwrap_or(0), email_id)?),
+        None => Ok(subscription.email_id == email_id && subscription.household_id.is_none()),
+    }
+}
+
+// Whether removing the member or changing their role leaves the household without an owner
+pub fn is_last_owner(
+    conn: &SqliteConnection,
+    household_id: i32,
+    email_id: i32,
+) -> QueryResult<bool> {
+    let household_members = members(conn, vec![household_id])?;
+    let owners: Vec<&HouseholdMember> = household_members
+        .iter()
+        .filter(|member| member.role == OWNER)
+        .collect();
+
+    Ok(owners.len() == 1 && owners[0].email_id == email_id)
+}
+
+// Joins the household the invite is for with its role, as the email it was sent to
+pub fn accept_invite(
+    conn: &SqliteConnection,
+    invite: &HouseholdInvite,
+) -> QueryResult<HouseholdMember> {
+    conn.transaction(|| {
+        let email_id = match email_id_of(conn, &invite.email)? {
+            Some(email_id) => email_id,
+            None => {
+                use crate::schema::emails::dsl::*;
+                diesel::insert_into(emails)
+                    .values(email.eq(invite.email.trim()))
+                    .execute(conn)?;
+                diesel::select(price::last_insert_rowid).get_result::<i32>(conn)?
+            }
+        };
+
+        {
+            use crate::schema::household_members::dsl;
+
+            diesel::insert_or_ignore_into(dsl::household_members)
+                .values(&HouseholdMember {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    household_id: invite.household_id,
+                    email_id,
+                    role: invite.role.clone(),
+                })
+                .execute(conn)?;
+
+            // Already a member, the invite only changes the role. Not for the last owner, or
+            // nobody could manage the household anymore.
+            if !is_last_owner(conn, invite.household_id, email_id)? {
+                diesel::update(
+                    dsl::household_members
+                        .filter(dsl::household_id.eq(invite.household_id))
+                        .filter(dsl::email_id.eq(email_id)),
+                )
+                .set(dsl::role.eq(&invite.role))
+                .execute(conn)?;
+            }
+        }
+
+        diesel::delete(invite).execute(conn)?;
+
+        use crate::schema::household_members::dsl;
+        dsl::household_members
+            .filter(dsl::household_id.eq(invite.household_id))
+            .filter(dsl::email_id.eq(email_id))
+            .first::<HouseholdMember>(conn)
+    })
+}
+
+// The email and everyone it shares a household with
+pub fn related_email_ids(conn: &SqliteConnection, email_id: i32) -> QueryResult<Vec<i32>> {
+    let mut email_ids: Vec<i32> = members(conn, household_ids(conn, email_id)?)?
+        .into_iter()
+        .map(|member| member.email_id)
+        .collect();
+    email_ids.push(email_id);
+    email_ids.sort_unstable();
+    email_ids.dedup();
+
+    Ok(email_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_subscription;
+    use crate::{establish_pool, run_migrations};
+    use diesel::connection::SimpleConnection;
+
+    fn member(email_id: i32) -> HouseholdMember {
+        HouseholdMember {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            household_id: 1,
+            email_id,
+            role: String::from(EDITOR),
+        }
+    }
+
+    fn share(email_id: i32, ratio: Option<f32>, fixed_amount: Option<f32>) -> SubscriptionShare {
+        SubscriptionShare {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            subscription_id: 1,
+            email_id,
+            ratio,
+            fixed_amount,
+        }
+    }
+
+    // Shares of emails 1 (the owner), 2 and 3 of a subscription costing 30
+    fn shares_of(shares: &[SubscriptionShare]) -> Vec<f32> {
+        let mut subscription = test_subscription(1, 1, 30.0);
+        subscription.household_id = Some(1);
+        let members = vec![member(1), member(2), member(3)];
+
+        (1..=3)
+            .map(|email_id| share_of(&subscription, &members, shares, email_id))
+            .map(|fraction| (fraction * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    #[test]
+    fn splits_shared_costs() {
+        let unshared = test_subscription(1, 1, 30.0);
+        assert_eq!(share_of(&unshared, &[], &[], 1), 1.0);
+        assert_eq!(share_of(&unshared, &[], &[], 2), 0.0);
+
+        // Equally without explicit shares
+        assert_eq!(shares_of(&[]), vec![0.333, 0.333, 0.333]);
+
+        // Fixed amounts first, ratios split the rest
+        assert_eq!(
+            shares_of(&[
+                share(2, None, Some(10.0)),
+                share(1, Some(1.0), None),
+                share(3, Some(1.0), None),
+            ]),
+            vec![0.333, 0.333, 0.333]
+        );
+        assert_eq!(
+            shares_of(&[share(2, None, Some(6.0)), share(3, Some(3.0), None)]),
+            vec![0.0, 0.2, 0.8]
+        );
+
+        // The owner carries what nobody else does
+        assert_eq!(
+            shares_of(&[share(2, None, Some(10.0))]),
+            vec![0.667, 0.333, 0.0]
+        );
+
+        // Fixed amounts over the cost are scaled down to it
+        assert_eq!(
+            shares_of(&[share(2, None, Some(40.0)), share(3, None, Some(20.0))]),
+            vec![0.0, 0.667, 0.333]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_owner() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+        conn.batch_execute(
+            "insert into emails (email) values ('a@x.com'), ('b@x.com');
+            insert into households (name) values ('Home');
+            insert into household_members (household_id, email_id, role)
+                values (1, 1, 'owner'), (1, 2, 'editor');
+            insert into household_invites (household_id, email, role)
+                values (1, 'a@x.com', 'viewer'), (1, 'b@x.com', 'viewer');",
+        )
+        .unwrap();
+
+        let accept = |address: &str| {
+            let invite = invites_for(&conn, address).unwrap().remove(0);
+            accept_invite(&conn, &invite).unwrap()
+        };
+        assert_eq!(accept("a@x.com").role, OWNER);
+        assert_eq!(accept("b@x.com").role, VIEWER);
+    }
+}
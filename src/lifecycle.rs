@@ -0,0 +1,166 @@
+use chrono::NaiveDateTime;
+
+use crate::model::Subscription;
+
+pub const ACTIVE: &str = "active";
+pub const PAUSED: &str = "paused";
+pub const CANCELLED: &str = "cancelled";
+pub const ARCHIVED: &str = "archived";
+
+// Whether the subscription is billed at the given time
+pub fn is_active_at(subscription: &Subscription, at: NaiveDateTime) -> bool {
+    match subscription.status.as_str() {
+        PAUSED => {
+            let paused_from = subscription.status_changed_at;
+            let in_pause = paused_from.map(|paused_from| at >= paused_from).unwrap_or(true)
+                && subscription
+                    .paused_until
+                    .map(|paused_until| at < paused_until)
+                    .unwrap_or(true);
+
+            !in_pause
+        }
+        CANCELLED => subscription
+            .cancelled_at
+            .map(|cancelled_at| at < cancelled_at)
+            .unwrap_or(false),
+        ARCHIVED => subscription
+            .cancelled_at
+            .or(subscription.status_changed_at)
+            .map(|ended_at| at < ended_at)
+            .unwrap_or(false),
+        _ => true,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub status: String,
+    pub paused_until: Option<NaiveDateTime>,
+    pub effective_at: Option<NaiveDateTime>,
+}
+
+// Applies a status change, returning the updated subscription or why it isn't allowed
+pub fn transition(
+    subscription: &Subscription,
+    change: &StatusChange,
+    now: NaiveDateTime,
+) -> Result<Subscription, String> {
+    let current = subscription.status.as_str();
+    let mut updated = subscription.clone();
+
+    updated.status_changed_at = Some(now);
+
+    match (current, change.status.as_str()) {
+        (PAUSED, ACTIVE) | (CANCELLED, ACTIVE) => {
+            updated.paused_until = None;
+            updated.cancelled_at = None;
+        }
+        (ACTIVE, PAUSED) | (PAUSED, PAUSED) => match change.paused_until {
+            Some(paused_until) if paused_until > now => {
+                // Keep the original pause start when only extending the pause
+                if current == PAUSED {
+                    updated.status_changed_at = subscription.status_changed_at;
+                }
+                updated.paused_until = Some(paused_until);
+            }
+            _ => return Err(String::from("paused_until must be in the future")),
+        },
+        (ACTIVE, CANCELLED) | (PAUSED, CANCELLED) | (CANCELLED, CANCELLED) => {
+            updated.paused_until = None;
+            updated.cancelled_at = Some(change.effective_at.unwrap_or(now));
+        }
+        (CANCELLED, ARCHIVED) => {}
+        (ARCHIVED, CANCELLED) => {
+            if updated.cancelled_at.is_none() {
+                updated.cancelled_at = subscription.status_changed_at.or(Some(now));
+            }
+        }
+        (from, to) => {
+            return Err(format!(
+                "Cannot change subscription status from {} to {}",
+                from, to
+            ))
+        }
+    }
+
+    updated.status = change.status.clone();
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::test_subscription;
+    use chrono::NaiveDate;
+
+    fn day(month_day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2026, 3, month_day).and_hms(0, 0, 0)
+    }
+
+    fn change(status: &str, paused_until: Option<NaiveDateTime>) -> StatusChange {
+        StatusChange {
+            status: status.to_string(),
+            paused_until,
+            effective_at: None,
+        }
+    }
+
+    #[test]
+    fn pauses_and_resumes() {
+        let active = test_subscription(1, 1, 10.0);
+
+        assert!(transition(&active, &change(PAUSED, Some(day(1))), day(5)).is_err());
+        assert!(transition(&active, &change(PAUSED, None), day(5)).is_err());
+
+        let paused = transition(&active, &change(PAUSED, Some(day(20))), day(5)).unwrap();
+        assert_eq!(paused.status_changed_at, Some(day(5)));
+        assert!(is_active_at(&paused, day(4)));
+        assert!(!is_active_at(&paused, day(5)));
+        assert!(!is_active_at(&paused, day(19)));
+        // Billed again once the pause is over, without being resumed
+        assert!(is_active_at(&paused, day(20)));
+
+        // Extending keeps when the pause started
+        let extended = transition(&paused, &change(PAUSED, Some(day(25))), day(10)).unwrap();
+        assert_eq!(extended.status_changed_at, Some(day(5)));
+        assert!(!is_active_at(&extended, day(22)));
+
+        let resumed = transition(&extended, &change(ACTIVE, None), day(12)).unwrap();
+        assert_eq!(resumed.paused_until, None);
+        assert!(is_active_at(&resumed, day(15)));
+    }
+
+    #[test]
+    fn cancels_and_archives() {
+        let active = test_subscription(1, 1, 10.0);
+
+        let cancelled = transition(
+            &active,
+            &StatusChange {
+                effective_at: Some(day(15)),
+                ..change(CANCELLED, None)
+            },
+            day(5),
+        )
+        .unwrap();
+        assert_eq!(cancelled.cancelled_at, Some(day(15)));
+        // Still billed until it takes effect
+        assert!(is_active_at(&cancelled, day(14)));
+        assert!(!is_active_at(&cancelled, day(15)));
+
+        let archived = transition(&cancelled, &change(ARCHIVED, None), day(20)).unwrap();
+        assert!(!is_active_at(&archived, day(20)));
+        assert!(is_active_at(&archived, day(14)));
+        assert!(transition(&archived, &change(ACTIVE, None), day(21)).is_err());
+        assert!(transition(&active, &change(ARCHIVED, None), day(21)).is_err());
+
+        let unarchived = transition(&archived, &change(CANCELLED, None), day(21)).unwrap();
+        assert_eq!(unarchived.cancelled_at, Some(day(15)));
+
+        let resumed = transition(&cancelled, &change(ACTIVE, None), day(20)).unwrap();
+        assert_eq!(resumed.cancelled_at, None);
+        assert!(is_active_at(&resumed, day(25)));
+    }
+}
@@ -1,5 +1,7 @@
 use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 
+use diesel::prelude::*;
+
 use crate::schema::*;
 
 // macro_rules! gen_struct {
@@ -25,57 +27,498 @@ use crate::schema::*;
 
 // gen_struct!(
 //     Email {
-//         email: Option<String>,
-//         currency_id: Option<i32>,
+//         email: String,
 //         currencie_id: Option<i32>,
 //     }
 // );
 
-#[derive(Identifiable, Queryable, Associations, Insertable, Debug, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Associations, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
 #[belongs_to(Currencie)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Email {
     pub id: Option<i32>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
-    pub email: Option<String>,
-    pub currency_id: Option<i32>,
+    pub email: String,
     pub currencie_id: Option<i32>,
+    #[serde(default)]
+    pub version: i32,
 }
 
-#[derive(Identifiable, Queryable, Insertable, Associations, Debug, Serialize, Deserialize)]
-#[belongs_to(Email)]
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Subscription {
     pub id: Option<i32>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
-    pub email_id: Option<i32>,
-    pub name: Option<String>,
-    pub cost: Option<f32>,
+    pub email_id: i32,
+    pub name: String,
+    pub cost: f32,
     pub interval_id: Option<i32>,
-    pub interval_amount: Option<i32>,
+    pub interval_amount: i32,
     pub currencie_id: Option<i32>,
+    pub category_id: Option<i32>,
+    pub trial_ends_at: Option<NaiveDateTime>,
+    pub promo_cost: Option<f32>,
+    pub promo_months: Option<i32>,
+    #[serde(default = "default_status")]
+    pub status: String,
+    pub paused_until: Option<NaiveDateTime>,
+    pub cancelled_at: Option<NaiveDateTime>,
+    pub status_changed_at: Option<NaiveDateTime>,
+    pub household_id: Option<i32>,
+    #[serde(default)]
+    pub version: i32,
+}
+
+fn default_status() -> String {
+    String::from("active")
 }
 
-#[derive(Identifiable, Queryable, Insertable, Debug, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Interval {
     pub id: Option<i32>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
-    pub name: Option<String>,
-    pub modifier: Option<f32>
+    pub name: String,
+    pub modifier: f32,
 }
 
-#[derive(Identifiable, Queryable, Insertable, Debug, Serialize, Deserialize)]
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Currencie {
     pub id: Option<i32>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
-    pub name: Option<String>,
-    pub rate: Option<f32>,
+    pub name: String,
+    pub rate: f32,
     pub last_update_day: Option<NaiveDateTime>,
+    // Pinned by an admin, the provider's rate is then kept in provider_rate only
+    pub manual_rate: Option<f32>,
+    pub provider_rate: Option<f32>,
+    // Not from the provider, like loyalty points
+    #[serde(default)]
+    pub custom: bool,
+    pub display_name: Option<String>,
+    pub symbol: Option<String>,
+    // Digits after the decimal point, 0 for JPY and 3 for BHD
+    pub minor_units: i32,
+    // False for withdrawn codes
+    pub active: bool,
 }
+
 // gen_struct!(
 //     Currencie {
 //         name: Option<String>,
 //     }
 // );
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[table_name = "categories"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Category {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub name: String,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Tag {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub subscription_id: i32,
+    pub name: String,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct SpendingSnapshot {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub currencie_id: Option<i32>,
+    pub snapshot_day: NaiveDateTime,
+    pub monthly_cost: f32,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+pub struct SubscriptionPrice {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub subscription_id: i32,
+    pub cost: f32,
+    pub currencie_id: Option<i32>,
+    pub interval_id: Option<i32>,
+    pub interval_amount: i32,
+    pub effective_from: NaiveDateTime,
+    pub effective_to: Option<NaiveDateTime>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Household {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub name: String,
+    pub currencie_id: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct HouseholdMember {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub household_id: i32,
+    pub email_id: i32,
+    pub role: String,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct HouseholdInvite {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub household_id: i32,
+    pub email: String,
+    pub role: String,
+    pub invited_by: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct SubscriptionShare {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub subscription_id: i32,
+    pub email_id: i32,
+    pub ratio: Option<f32>,
+    pub fixed_amount: Option<f32>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+pub struct Budget {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub category_id: Option<i32>,
+    pub monthly_limit: f32,
+    #[serde(default)]
+    pub is_over: bool,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct BudgetAlert {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub budget_id: i32,
+    pub email_id: i32,
+    pub monthly_limit: f32,
+    pub spent: f32,
+    pub is_over: bool,
+    pub source: String,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct Webhook {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub event_types: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub webhook_id: i32,
+    pub event_type: String,
+    pub event_key: Option<String>,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, AsChangeset, Clone, Debug)]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct LocalAccount {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub password_hash: String,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug)]
+pub struct RateLimitBucket {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub bucket_key: String,
+    pub tokens: f64,
+    pub refilled_at: f64,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug)]
+pub struct Session {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email: String,
+    pub refresh_token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize)]
+pub struct ApiKey {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub read_only: bool,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize, Deserialize)]
+pub struct RateOverride {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: i32,
+    pub currencie_id: i32,
+    pub rate: f32,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Clone, Debug, Serialize)]
+pub struct AuditLog {
+    pub id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub email_id: Option<i32>,
+    pub actor: String,
+    pub action: String,
+    pub entity: String,
+    pub entity_id: Option<i32>,
+    pub before_json: Option<String>,
+    pub after_json: Option<String>,
+    pub request_id: Option<String>,
+}
+
+// Updates the row in place when it exists so created_at is kept, otherwise inserts it. None
+// clears a column on update, so the timestamps are taken from the stored row; updated_at is
+// then maintained by the after update triggers.
+macro_rules! impl_save {
+    ($struct:ident, $table:ident) => {
+        impl $struct {
+            pub fn save(&self, conn: &SqliteConnection) -> QueryResult<usize> {
+                use crate::schema::$table::dsl::$table;
+
+                let stored = match self.id {
+                    Some(row_id) => $table.find(row_id).first::<$struct>(conn).optional()?,
+                    None => None,
+                };
+
+                match stored {
+                    Some(stored) => {
+                        let row = $struct {
+                            created_at: stored.created_at,
+                            updated_at: stored.updated_at,
+                            ..self.clone()
+                        };
+                        diesel::update(&stored).set(&row).execute(conn)
+                    }
+                    None => {
+                        let row = $struct {
+                            created_at: None,
+                            updated_at: None,
+                            ..self.clone()
+                        };
+                        diesel::insert_into($table).values(&row).execute(conn)
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_save!(Email, emails);
+impl_save!(Subscription, subscriptions);
+impl_save!(Interval, intervals);
+impl_save!(Currencie, currencies);
+impl_save!(Category, categories);
+impl_save!(Tag, tags);
+impl_save!(Household, households);
+impl_save!(Webhook, webhooks);
+impl_save!(LocalAccount, local_accounts);
+
+impl Subscription {
+    // Deletes the subscription along with the rows referencing it
+    pub fn delete(conn: &SqliteConnection, subscription_id: i32) -> QueryResult<usize> {
+        {
+            use crate::schema::tags::dsl;
+            diesel::delete(dsl::tags.filter(dsl::subscription_id.eq(subscription_id)))
+                .execute(conn)?;
+        }
+        {
+            use crate::schema::subscription_shares::dsl;
+            diesel::delete(
+                dsl::subscription_shares.filter(dsl::subscription_id.eq(subscription_id)),
+            )
+            .execute(conn)?;
+        }
+        {
+            use crate::schema::subscription_prices::dsl;
+            diesel::delete(
+                dsl::subscription_prices.filter(dsl::subscription_id.eq(subscription_id)),
+            )
+            .execute(conn)?;
+        }
+
+        use crate::schema::subscriptions::dsl::subscriptions;
+        diesel::delete(subscriptions.find(subscription_id)).execute(conn)
+    }
+}
+
+// An active monthly subscription created on 2026-01-01, for tests to adjust
+#[cfg(test)]
+pub fn test_subscription(id: i32, email_id: i32, cost: f32) -> Subscription {
+    Subscription {
+        id: Some(id),
+        created_at: Some(NaiveDate::from_ymd(2026, 1, 1).and_hms(0, 0, 0)),
+        updated_at: None,
+        email_id,
+        name: String::from("Music"),
+        cost,
+        interval_id: None,
+        interval_amount: 1,
+        currencie_id: None,
+        category_id: None,
+        trial_ends_at: None,
+        promo_cost: None,
+        promo_months: None,
+        status: String::from("active"),
+        paused_until: None,
+        cancelled_at: None,
+        status_changed_at: None,
+        household_id: None,
+        version: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{establish_pool, run_migrations};
+
+    #[test]
+    fn save_clears_nullable_columns() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+
+        let mut email = Email {
+            id: Some(1),
+            created_at: None,
+            updated_at: None,
+            email: String::from("a@x.com"),
+            currencie_id: Some(1),
+            version: 0,
+        };
+        email.save(&conn).unwrap();
+
+        let saved = {
+            use crate::schema::emails::dsl::*;
+            emails.find(1).first::<Email>(&conn).unwrap()
+        };
+        assert_eq!(saved.currencie_id, Some(1));
+
+        email.currencie_id = None;
+        email.version = saved.version;
+        email.save(&conn).unwrap();
+
+        let cleared = {
+            use crate::schema::emails::dsl::*;
+            emails.find(1).first::<Email>(&conn).unwrap()
+        };
+        assert_eq!(cleared.currencie_id, None);
+        assert_eq!(cleared.created_at, saved.created_at);
+        assert!(cleared.updated_at.is_some());
+        assert_eq!(cleared.version, saved.version + 1);
+
+        Category {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            email_id: 1,
+            name: String::from("Streaming"),
+        }
+        .save(&conn)
+        .unwrap();
+
+        let mut subscription = Subscription {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            email_id: 1,
+            name: String::from("Music"),
+            cost: 10.0,
+            interval_id: None,
+            interval_amount: 1,
+            currencie_id: None,
+            category_id: Some(1),
+            trial_ends_at: None,
+            promo_cost: Some(5.0),
+            promo_months: Some(3),
+            status: String::from("active"),
+            paused_until: None,
+            cancelled_at: None,
+            status_changed_at: None,
+            household_id: None,
+            version: 0,
+        };
+        subscription.id = Some(crate::price::save_subscription(&conn, &subscription).unwrap());
+
+        subscription.category_id = None;
+        subscription.promo_cost = None;
+        subscription.promo_months = None;
+        crate::price::save_subscription(&conn, &subscription).unwrap();
+
+        let saved = {
+            use crate::schema::subscriptions::dsl::*;
+            subscriptions.first::<Subscription>(&conn).unwrap()
+        };
+        assert_eq!(saved.category_id, None);
+        assert_eq!((saved.promo_cost, saved.promo_months), (None, None));
+    }
+}
@@ -0,0 +1,329 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// OpenID Connect providers besides Google, configured in env.json like
+// {"name": "Keycloak", "issuer": "https://sso.example.com/realms/home", "client_id": "monty"}.
+// Their id tokens are verified locally against the issuer's published signing keys.
+
+// Refetched after this, or sooner when a token names a key we haven't seen
+const KEYS_MAX_AGE: Duration = Duration::from_secs(3600);
+// Unknown key ids can't make us hammer the issuer
+const KEYS_MIN_AGE: Duration = Duration::from_secs(60);
+
+// Only asymmetric algorithms, a token can't pick HS256 and sign with the public key
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    // Found through discovery when not set
+    #[serde(default, skip_serializing)]
+    pub jwks_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    email: Option<String>,
+    // Some providers send "true" as a string
+    email_verified: Option<Value>,
+}
+
+struct CachedKeys {
+    fetched: Instant,
+    keys: JwkSet,
+}
+
+static KEYS: OnceLock<Mutex<HashMap<String, CachedKeys>>> = OnceLock::new();
+
+fn same_issuer(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+// Who claims to have issued the token, before anything is verified
+pub fn issuer(id_token: &str) -> Option<String> {
+    let payload = id_token.split('.').nth(1)?;
+    let bytes = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+
+    serde_json::from_slice::<Value>(&bytes).ok()?["iss"]
+        .as_str()
+        .map(String::from)
+}
+
+pub fn provider_for<'a>(providers: &'a [Provider], issuer: &str) -> Option<&'a Provider> {
+    providers
+        .iter()
+        .find(|provider| same_issuer(&provider.issuer, issuer))
+}
+
+async fn fetch_keys(provider: &Provider) -> Result<JwkSet, String> {
+    let jwks_uri = match &provider.jwks_uri {
+        Some(jwks_uri) => jwks_uri.clone(),
+        None => {
+            let url = format!(
+                "{}/.well-known/openid-configuration",
+                provider.issuer.trim_end_matches('/')
+            );
+            let discovery = reqwest::get(url.as_str())
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| e.to_string())?
+                .json::<Discovery>()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if !same_issuer(&discovery.issuer, &provider.issuer) {
+                return Err(format!("Discovery is for issuer {}", discovery.issuer));
+            }
+
+            discovery.jwks_uri
+        }
+    };
+
+    reqwest::get(jwks_uri.as_str())
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json::<JwkSet>()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn cached_key(issuer: &str, kid: &Option<String>, max_age: Duration) -> Option<DecodingKey> {
+    let keys = KEYS.get_or_init(Default::default).lock().ok()?;
+    let cached = keys.get(issuer)?;
+
+    if cached.fetched.elapsed() > max_age {
+        return None;
+    }
+
+    let jwk = match kid {
+        Some(kid) => cached.keys.find(kid),
+        None if cached.keys.keys.len() == 1 => cached.keys.keys.first(),
+        None => None,
+    }?;
+
+    DecodingKey::from_jwk(jwk).ok()
+}
+
+async fn signing_key(provider: &Provider, kid: &Option<String>) -> Result<DecodingKey, String> {
+    if let Some(key) = cached_key(&provider.issuer, kid, KEYS_MAX_AGE) {
+        return Ok(key);
+    }
+
+    let recently_fetched = KEYS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|e| e.to_string())?
+        .get(&provider.issuer)
+        .map(|cached| cached.fetched.elapsed() < KEYS_MIN_AGE)
+        .unwrap_or(false);
+
+    if !recently_fetched {
+        let keys = fetch_keys(provider).await?;
+
+        KEYS.get_or_init(Default::default)
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(
+                provider.issuer.clone(),
+                CachedKeys {
+                    fetched: Instant::now(),
+                    keys,
+                },
+            );
+    }
+
+    cached_key(&provider.issuer, kid, KEYS_MAX_AGE)
+        .ok_or_else(|| format!("Unknown signing key {:?}", kid))
+}
+
+// The email an id token from the provider was issued for, checking its signature, issuer,
+// audience and expiry
+pub async fn verify(provider: &Provider, id_token: &str) -> Result<String, String> {
+    let header = decode_header(id_token).map_err(|e| e.to_string())?;
+
+    if !ALGORITHMS.contains(&header.alg) {
+        return Err(format!("Algorithm {:?} not allowed", header.alg));
+    }
+
+    let key = signing_key(provider, &header.kid).await?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_issuer(&[&provider.issuer, provider.issuer.trim_end_matches('/')]);
+
+    let claims = decode::<Claims>(id_token, &key, &validation)
+        .map_err(|e| e.to_string())?
+        .claims;
+
+    let verified = match claims.email_verified {
+        Some(Value::Bool(verified)) => verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => true,
+    };
+
+    match claims.email {
+        Some(email) if verified => Ok(email),
+        Some(_) => Err(String::from("Email not verified")),
+        None => Err(String::from("No email claim")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::json;
+
+    const CLIENT_ID: &str = "monty";
+    const KID: &str = "test-key";
+
+    struct Signer {
+        private_pem: Vec<u8>,
+        n: String,
+        e: String,
+    }
+
+    fn signer() -> Signer {
+        let rsa = Rsa::generate(2048).unwrap();
+        let encode_part = |bytes: Vec<u8>| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+
+        Signer {
+            private_pem: rsa.private_key_to_pem().unwrap(),
+            n: encode_part(rsa.n().to_vec()),
+            e: encode_part(rsa.e().to_vec()),
+        }
+    }
+
+    async fn discovery(req: HttpRequest) -> HttpResponse {
+        let issuer = format!("http://{}", req.connection_info().host());
+        HttpResponse::Ok().json(json!({"issuer": issuer, "jwks_uri": format!("{}/jwks", issuer)}))
+    }
+
+    async fn jwks(jwks: web::Data<Value>) -> HttpResponse {
+        HttpResponse::Ok().json(jwks.get_ref())
+    }
+
+    // A local issuer publishing discovery and the signer's public key
+    fn mock_issuer(signer: &Signer) -> test::TestServer {
+        let keys = json!({
+            "keys": [{"kty": "RSA", "use": "sig", "alg": "RS256", "kid": KID, "n": signer.n, "e": signer.e}]
+        });
+
+        test::start(move || {
+            App::new()
+                .data(keys.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/jwks", web::get().to(jwks))
+        })
+    }
+
+    fn provider(srv: &test::TestServer) -> Provider {
+        Provider {
+            name: String::from("Mock"),
+            issuer: srv.url("").trim_end_matches('/').to_string(),
+            client_id: String::from(CLIENT_ID),
+            jwks_uri: None,
+        }
+    }
+
+    fn id_token(signer: &Signer, provider: &Provider, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(String::from(KID));
+
+        let now = chrono::Utc::now().timestamp();
+        let mut all_claims = json!({
+            "iss": provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": "1234",
+            "email": "a@x.com",
+            "iat": now,
+            "exp": now + 300,
+        });
+        for (claim, val) in claims.as_object().unwrap() {
+            all_claims[claim] = val.clone();
+        }
+
+        let key = EncodingKey::from_rsa_pem(&signer.private_pem).unwrap();
+        encode(&header, &all_claims, &key).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn verifies_tokens_from_the_issuer() {
+        let signer = signer();
+        let srv = mock_issuer(&signer);
+        let provider = provider(&srv);
+
+        let token = id_token(&signer, &provider, json!({}));
+
+        assert_eq!(issuer(&token), Some(provider.issuer.clone()));
+        assert_eq!(verify(&provider, &token).await, Ok(String::from("a@x.com")));
+    }
+
+    #[actix_rt::test]
+    async fn rejects_bad_claims() {
+        let signer = signer();
+        let srv = mock_issuer(&signer);
+        let provider = provider(&srv);
+
+        let now = chrono::Utc::now().timestamp();
+        for claims in [
+            json!({"aud": "someone-else"}),
+            json!({"iss": "https://evil.example.com"}),
+            json!({"exp": now - 3600}),
+            json!({"email_verified": false}),
+        ] {
+            let token = id_token(&signer, &provider, claims.clone());
+            assert!(verify(&provider, &token).await.is_err(), "{}", claims);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rejects_other_signers() {
+        let signer_a = signer();
+        let srv = mock_issuer(&signer_a);
+        let provider = provider(&srv);
+
+        // Same key id, different key
+        let token = id_token(&signer(), &provider, json!({}));
+        assert!(verify(&provider, &token).await.is_err());
+
+        // Signed with the public key as an HMAC secret
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(String::from(KID));
+        let claims = json!({"iss": provider.issuer, "aud": CLIENT_ID, "email": "a@x.com", "exp": chrono::Utc::now().timestamp() + 300});
+        let token = encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(signer_a.n.as_bytes()),
+        )
+        .unwrap();
+        assert!(verify(&provider, &token).await.is_err());
+    }
+}
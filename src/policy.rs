@@ -0,0 +1,476 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized},
+    http::Method,
+    web, Error, HttpMessage,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::auth::{self, AuthEmail, Identity};
+use crate::{assets, config::Config, DbPool};
+
+// Who may call a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Public,
+    Authenticated,
+    Admin,
+}
+
+// A method (None for any) and a path pattern, where {name} matches one segment and a trailing
+// /* any rest of the path
+#[derive(Debug, Clone)]
+pub struct Rule {
+    method: Option<Method>,
+    pattern: String,
+    access: Access,
+}
+
+impl Rule {
+    pub fn new(method: Option<Method>, pattern: &str, access: Access) -> Rule {
+        Rule {
+            method,
+            pattern: pattern.to_string(),
+            access,
+        }
+    }
+
+    // "GET /path" or "/path" for any method
+    pub fn parse(route: &str, access: Access) -> Option<Rule> {
+        let mut parts = route.split_whitespace();
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(pattern), None, None) if pattern.starts_with('/') => {
+                Some(Rule::new(None, pattern, access))
+            }
+            (Some(method), Some(pattern), None) if pattern.starts_with('/') => {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).ok()?;
+                Some(Rule::new(Some(method), pattern, access))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        if let Some(rule_method) = &self.method {
+            if rule_method != method {
+                return false;
+            }
+        }
+
+        let mut pattern_segments = segments(&self.pattern);
+        let mut path_segments = segments(path);
+
+        loop {
+            match (pattern_segments.next(), path_segments.next()) {
+                (Some("*"), _) => return true,
+                (Some(pattern_segment), Some(path_segment)) => {
+                    let is_param =
+                        pattern_segment.starts_with('{') && pattern_segment.ends_with('}');
+                    if !is_param && pattern_segment != path_segment {
+                        return false;
+                    }
+                }
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+// Trailing slashes don't make a different route
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+// Every route that isn't for signed in users, first match wins. Anything not listed here needs
+// a signed in user, and frontend files are always public.
+pub fn default_rules() -> Vec<Rule> {
+    vec![
+        Rule::new(Some(Method::GET), "/currencies", Access::Public),
+        Rule::new(Some(Method::POST), "/google-login-verify", Access::Public),
+        Rule::new(Some(Method::POST), "/login-verify", Access::Public),
+        Rule::new(Some(Method::GET), "/login-providers", Access::Public),
+        Rule::new(Some(Method::POST), "/login", Access::Public),
+        Rule::new(Some(Method::POST), "/sessions/refresh", Access::Public),
+        // Everyone's emails and subscriptions
+        Rule::new(Some(Method::GET), "/emails", Access::Admin),
+        Rule::new(Some(Method::GET), "/subscriptions", Access::Admin),
+        // Currencies and rates everyone uses
+        Rule::new(Some(Method::POST), "/currencies", Access::Admin),
+        Rule::new(Some(Method::POST), "/currencies/{id}/rate", Access::Admin),
+        Rule::new(Some(Method::GET), "/rates/status", Access::Admin),
+    ]
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    admin_emails: Vec<String>,
+}
+
+impl Policy {
+    pub fn new(rules: Vec<Rule>, admin_emails: Vec<String>) -> Policy {
+        Policy {
+            rules,
+            admin_emails: admin_emails
+                .iter()
+                .map(|admin_email| admin_email.trim().to_lowercase())
+                .collect(),
+        }
+    }
+
+    // The default rules, then public_routes from env.json
+    pub fn from_config(config: &Config) -> Policy {
+        let mut rules = default_rules();
+
+        for route in &config.public_routes {
+            match Rule::parse(route, Access::Public) {
+                Some(rule) => rules.push(rule),
+                None => println!("Ignoring invalid public route {:?}", route),
+            }
+        }
+
+        Policy::new(rules, config.admin_emails.clone())
+    }
+
+    pub fn access(&self, method: &Method, path: &str) -> Access {
+        if method == Method::GET && assets::is_asset(path) {
+            return Access::Public;
+        }
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(method, path))
+            .map(|rule| rule.access)
+            .unwrap_or(Access::Authenticated)
+    }
+
+    pub fn is_admin(&self, email: &str) -> bool {
+        self.admin_emails.contains(&email.trim().to_lowercase())
+    }
+}
+
+// Checks an id token or API key, giving who it's for. Sessions and keys need the pool.
+pub type Verifier = fn(Option<DbPool>, String) -> LocalBoxFuture<'static, Option<Identity>>;
+
+fn token_verifier(
+    pool: Option<DbPool>,
+    id_token: String,
+) -> LocalBoxFuture<'static, Option<Identity>> {
+    Box::pin(async move { auth::identify(pool.as_ref(), &id_token).await })
+}
+
+// Enforces the policy before the handler runs. The verified email is left in the request
+// extensions for AuthEmail, so the token is only checked once.
+pub struct Authorize {
+    policy: Rc<Policy>,
+    verifier: Verifier,
+}
+
+impl Authorize {
+    pub fn new(policy: Policy) -> Authorize {
+        Authorize::with_verifier(policy, token_verifier)
+    }
+
+    pub fn with_verifier(policy: Policy, verifier: Verifier) -> Authorize {
+        Authorize {
+            policy: Rc::new(policy),
+            verifier,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Authorize
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthorizeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthorizeMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            policy: self.policy.clone(),
+            verifier: self.verifier,
+        })
+    }
+}
+
+pub struct AuthorizeMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    policy: Rc<Policy>,
+    verifier: Verifier,
+}
+
+impl<S, B> Service for AuthorizeMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let policy = self.policy.clone();
+        let verifier = self.verifier;
+
+        Box::pin(async move {
+            let access = policy.access(req.method(), req.path());
+
+            if access != Access::Public {
+                let id_token = match auth::id_token(req.headers(), req.path(), req.query_string()) {
+                    Some(id_token) => id_token,
+                    None => return Err(ErrorBadRequest("No auth header present!")),
+                };
+
+                let pool = req
+                    .app_data::<web::Data<DbPool>>()
+                    .map(|pool| pool.get_ref().clone());
+
+                let identity = match verifier(pool, id_token).await {
+                    Some(identity) => identity,
+                    None => return Err(ErrorUnauthorized("Unauthorized!")),
+                };
+
+                if !identity.may(req.method()) {
+                    return Err(ErrorForbidden(auth::READ_ONLY));
+                }
+                let email = identity.email;
+
+                if access == Access::Admin && !policy.is_admin(&email) {
+                    return Err(ErrorForbidden("Admins only"));
+                }
+
+                req.extensions_mut().insert(AuthEmail(email));
+            }
+
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn stub_verifier(
+        _pool: Option<DbPool>,
+        id_token: String,
+    ) -> LocalBoxFuture<'static, Option<Identity>> {
+        Box::pin(async move {
+            let (email, read_only) = match id_token.as_str() {
+                "alice-token" => ("alice@example.com", false),
+                "root-token" => ("Root@Example.com", false),
+                "reader-key" => ("reader@example.com", true),
+                _ => return None,
+            };
+
+            Some(Identity {
+                email: String::from(email),
+                read_only,
+            })
+        })
+    }
+
+    fn policy() -> Policy {
+        let mut rules = default_rules();
+        rules.push(Rule::parse("GET /health", Access::Public).unwrap());
+        rules.push(Rule::parse("/public/*", Access::Public).unwrap());
+
+        Policy::new(rules, vec![String::from("root@example.com")])
+    }
+
+    #[test]
+    fn matches_exact_paths_and_methods() {
+        let rule = Rule::new(Some(Method::GET), "/currencies", Access::Public);
+
+        assert!(rule.matches(&Method::GET, "/currencies"));
+        assert!(rule.matches(&Method::GET, "/currencies/"));
+        assert!(!rule.matches(&Method::POST, "/currencies"));
+        assert!(!rule.matches(&Method::GET, "/currencies/1"));
+        assert!(!rule.matches(&Method::GET, "/currenciesx"));
+    }
+
+    #[test]
+    fn matches_params_and_wildcards() {
+        let param = Rule::new(None, "/emails/{email_id}/budgets", Access::Admin);
+        assert!(param.matches(&Method::DELETE, "/emails/3/budgets"));
+        assert!(!param.matches(&Method::GET, "/emails/3/webhooks"));
+        assert!(!param.matches(&Method::GET, "/emails/budgets"));
+
+        let wildcard = Rule::new(None, "/public/*", Access::Public);
+        assert!(wildcard.matches(&Method::GET, "/public/a/b"));
+        assert!(wildcard.matches(&Method::GET, "/public"));
+        assert!(!wildcard.matches(&Method::GET, "/publicity"));
+    }
+
+    #[test]
+    fn parses_configured_routes() {
+        let rule = Rule::parse("post /hooks/{id}", Access::Public).unwrap();
+        assert!(rule.matches(&Method::POST, "/hooks/1"));
+        assert!(!rule.matches(&Method::GET, "/hooks/1"));
+
+        assert!(Rule::parse("/any", Access::Public)
+            .unwrap()
+            .matches(&Method::PUT, "/any"));
+        assert!(Rule::parse("GET", Access::Public).is_none());
+        assert!(Rule::parse("GET health", Access::Public).is_none());
+        assert!(Rule::parse("GET /a /b", Access::Public).is_none());
+    }
+
+    #[test]
+    fn defaults_to_authenticated() {
+        let policy = policy();
+
+        assert_eq!(policy.access(&Method::GET, "/currencies"), Access::Public);
+        assert_eq!(policy.access(&Method::GET, "/health"), Access::Public);
+        assert_eq!(policy.access(&Method::GET, "/emails"), Access::Admin);
+        assert_eq!(policy.access(&Method::GET, "/emails/"), Access::Admin);
+        assert_eq!(
+            policy.access(&Method::GET, "/emails/1"),
+            Access::Authenticated
+        );
+        assert_eq!(policy.access(&Method::POST, "/currencies"), Access::Admin);
+        assert_eq!(
+            policy.access(&Method::POST, "/currencies/1/rate"),
+            Access::Admin
+        );
+        assert_eq!(
+            policy.access(&Method::GET, "/unknown"),
+            Access::Authenticated
+        );
+    }
+
+    #[test]
+    fn admins_are_case_insensitive() {
+        let policy = policy();
+
+        assert!(policy.is_admin("ROOT@example.com "));
+        assert!(!policy.is_admin("alice@example.com"));
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn counted(auth_email: Option<AuthEmail>) -> HttpResponse {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+
+        HttpResponse::Ok().body(
+            auth_email
+                .map(|auth_email| auth_email.0)
+                .unwrap_or_default(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn checks_before_the_handler_runs() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Authorize::with_verifier(policy(), stub_verifier))
+                .route("/health", web::get().to(counted))
+                .route("/emails", web::get().to(counted))
+                .route("/budgets", web::post().to(counted))
+                .route("/budgets", web::get().to(counted))
+                .route("/events", web::get().to(counted)),
+        )
+        .await;
+
+        let cases = vec![
+            (
+                test::TestRequest::post().uri("/budgets"),
+                StatusCode::BAD_REQUEST,
+            ),
+            // Only taken from the query string for EventSource
+            (
+                test::TestRequest::post().uri("/budgets?access_token=alice-token"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                test::TestRequest::post()
+                    .uri("/budgets")
+                    .header("authorization", "forged"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                test::TestRequest::get()
+                    .uri("/emails")
+                    .header("authorization", "alice-token"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                test::TestRequest::post()
+                    .uri("/budgets")
+                    .header("x-api-key", "reader-key"),
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+
+        for (req, expected) in cases {
+            let res = app.call(req.to_request()).await;
+            let status = match res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+
+            assert_eq!(status, expected);
+            assert_eq!(
+                CALLS.load(Ordering::SeqCst),
+                0,
+                "handler ran for {}",
+                expected
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        assert_eq!(
+            test::call_service(&mut app, req).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        let req = test::TestRequest::get()
+            .uri("/events?access_token=alice-token")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "alice@example.com");
+
+        let req = test::TestRequest::get()
+            .uri("/emails")
+            .header("authorization", "root-token")
+            .to_request();
+        assert_eq!(
+            test::call_service(&mut app, req).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        // Read only keys can still read
+        let req = test::TestRequest::get()
+            .uri("/budgets")
+            .header("x-api-key", "reader-key")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "reader@example.com");
+    }
+}
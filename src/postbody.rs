@@ -1,4 +1,4 @@
-use crate::model::{Email, Subscription};
+use crate::model::{Email, Household, Subscription};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmailPostBody {
@@ -6,3 +6,20 @@ pub struct EmailPostBody {
     pub subscriptions: Vec<Subscription>,
     pub subscription_delete_ids: Vec<i32>,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HouseholdPostBody {
+    pub household: Household,
+    pub email_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitePostBody {
+    pub email: String,
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RolePostBody {
+    pub role: String,
+}
@@ -0,0 +1,120 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+
+use crate::lifecycle;
+use crate::model::{Subscription, SubscriptionPrice};
+
+no_arg_sql_function!(
+    last_insert_rowid,
+    diesel::sql_types::Integer,
+    "Id of the last inserted row"
+);
+
+// Saves the subscription and records a new price when cost, currency or interval changed.
+// Status fields and the household are kept as stored, they only change through lifecycle
+// transitions and POST /subscriptions/{id}/shares.
+// Returns the saved subscription id.
+pub fn save_subscription(conn: &SqliteConnection, subscription: &Subscription) -> QueryResult<i32> {
+    conn.transaction(|| {
+        let stored = match subscription.id {
+            Some(subscription_id) => {
+                use crate::schema::subscriptions::dsl::subscriptions;
+                subscriptions
+                    .find(subscription_id)
+                    .first::<Subscription>(conn)
+                    .optional()?
+            }
+            None => None,
+        };
+
+        let subscription = &match stored {
+            Some(stored) => Subscription {
+                status: stored.status,
+                paused_until: stored.paused_until,
+                cancelled_at: stored.cancelled_at,
+                status_changed_at: stored.status_changed_at,
+                household_id: stored.household_id,
+                ..subscription.clone()
+            },
+            None => Subscription {
+                status: String::from(lifecycle::ACTIVE),
+                paused_until: None,
+                cancelled_at: None,
+                status_changed_at: None,
+                household_id: None,
+                version: 0,
+                ..subscription.clone()
+            },
+        };
+
+        subscription.save(conn)?;
+
+        let subscription_id = match subscription.id {
+            Some(subscription_id) => subscription_id,
+            None => diesel::select(last_insert_rowid).get_result::<i32>(conn)?,
+        };
+
+        record_price(conn, subscription_id, subscription, Utc::now().naive_utc())?;
+
+        Ok(subscription_id)
+    })
+}
+
+pub fn record_price(
+    conn: &SqliteConnection,
+    subscription_id: i32,
+    subscription: &Subscription,
+    effective_from: NaiveDateTime,
+) -> QueryResult<()> {
+    use crate::schema::subscription_prices::dsl;
+
+    let current_price = dsl::subscription_prices
+        .filter(dsl::subscription_id.eq(subscription_id))
+        .filter(dsl::effective_to.is_null())
+        .order(dsl::effective_from.desc())
+        .first::<SubscriptionPrice>(conn)
+        .optional()?;
+
+    if let Some(current_price) = &current_price {
+        if current_price.cost == subscription.cost
+            && current_price.currencie_id == subscription.currencie_id
+            && current_price.interval_id == subscription.interval_id
+            && current_price.interval_amount == subscription.interval_amount
+        {
+            return Ok(());
+        }
+
+        diesel::update(current_price)
+            .set(dsl::effective_to.eq(Some(effective_from)))
+            .execute(conn)?;
+    }
+
+    diesel::insert_into(dsl::subscription_prices)
+        .values(&SubscriptionPrice {
+            id: None,
+            created_at: None,
+            updated_at: None,
+            subscription_id,
+            cost: subscription.cost,
+            currencie_id: subscription.currencie_id,
+            interval_id: subscription.interval_id,
+            interval_amount: subscription.interval_amount,
+            effective_from,
+            effective_to: None,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn subscription_prices(
+    conn: &SqliteConnection,
+    subscription_ids: Vec<i32>,
+) -> QueryResult<Vec<SubscriptionPrice>> {
+    use crate::schema::subscription_prices::dsl;
+
+    dsl::subscription_prices
+        .filter(dsl::subscription_id.eq_any(subscription_ids))
+        .order(dsl::effective_from.asc())
+        .load::<SubscriptionPrice>(conn)
+}
@@ -0,0 +1,497 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    Error, HttpMessage, HttpResponse,
+};
+use chrono::Utc;
+use diesel::prelude::*;
+use futures::future::{err, ok, Either, Ready};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Once};
+use std::task::{Context, Poll};
+use tokio_diesel::*;
+
+use crate::auth::AuthEmail;
+use crate::model::RateLimitBucket;
+use crate::DbPool;
+
+// Token buckets per client IP, before auth so made up tokens can't be sent to Google as fast
+// as anyone likes, and per signed in user after it. Logins get a stricter bucket of their own.
+
+// Where tokens are checked without being signed in
+const LOGIN_PATHS: [&str; 4] = [
+    "/google-login-verify",
+    "/login-verify",
+    "/login",
+    "/sessions/refresh",
+];
+// Full buckets are dropped past this many, then the least recently used ones, so lots of IPs
+// can't use up memory
+const MAX_BUCKETS: usize = 10_000;
+const PERSIST_SECONDS: u64 = 30;
+
+const IP: &str = "ip";
+const USER: &str = "user";
+const LOGIN: &str = "login";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    // Requests allowed at once
+    pub burst: f64,
+    // Requests allowed over time after that
+    pub per_minute: f64,
+}
+
+impl Limit {
+    fn refill(&self, bucket: &mut Bucket, now: f64) {
+        let elapsed = (now - bucket.refilled_at).max(0.0);
+
+        bucket.tokens = (bucket.tokens + elapsed * self.per_minute / 60.0).min(self.burst);
+        bucket.refilled_at = now;
+    }
+}
+
+// From rate_limits in env.json, a limit set to null is off
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub ip: Option<Limit>,
+    pub user: Option<Limit>,
+    pub login: Option<Limit>,
+    // Take the client IP from the last X-Forwarded-For entry. On by default as the server only
+    // listens on 127.0.0.1, so it's always behind a proxy that adds it.
+    pub trust_proxy: bool,
+    // Keep buckets in the database across restarts
+    pub persist: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            ip: Some(Limit {
+                burst: 120.0,
+                per_minute: 600.0,
+            }),
+            user: Some(Limit {
+                burst: 60.0,
+                per_minute: 300.0,
+            }),
+            login: Some(Limit {
+                burst: 10.0,
+                per_minute: 10.0,
+            }),
+            trust_proxy: true,
+            persist: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: f64,
+    limit: Limit,
+}
+
+impl Bucket {
+    // On a copy, refilled_at says when the bucket was last used
+    fn is_full(&self, now: f64) -> bool {
+        let mut refilled = *self;
+        self.limit.refill(&mut refilled, now);
+        refilled.tokens >= self.limit.burst
+    }
+}
+
+pub struct Limiter {
+    settings: Settings,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+fn now_seconds() -> f64 {
+    Utc::now().timestamp_millis() as f64 / 1000.0
+}
+
+impl Limiter {
+    pub fn new(settings: Settings) -> Limiter {
+        Limiter {
+            settings,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit_for(&self, key: &str) -> Option<Limit> {
+        match key.split(':').next() {
+            Some(IP) => self.settings.ip,
+            Some(USER) => self.settings.user,
+            Some(LOGIN) => self.settings.login,
+            _ => None,
+        }
+    }
+
+    // Takes a token from the key's bucket, or says how many seconds until there is one
+    pub fn take(&self, key: &str, now: f64) -> Result<(), u64> {
+        let limit = match self.limit_for(key) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+
+            if buckets.len() >= MAX_BUCKETS {
+                // A tenth at a time, so this isn't done for every new key
+                let mut least_used: Vec<(f64, String)> = buckets
+                    .iter()
+                    .map(|(bucket_key, bucket)| (bucket.refilled_at, bucket_key.clone()))
+                    .collect();
+                least_used.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                for (_, bucket_key) in least_used.iter().take(MAX_BUCKETS / 10) {
+                    buckets.remove(bucket_key);
+                }
+            }
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit.burst,
+            refilled_at: now,
+            limit,
+        });
+        limit.refill(bucket, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if limit.per_minute > 0.0 {
+            Err(((1.0 - bucket.tokens) * 60.0 / limit.per_minute).ceil() as u64)
+        } else {
+            Err(60)
+        }
+    }
+
+    fn client_ip(&self, req: &ServiceRequest) -> Option<String> {
+        let addr = if self.settings.trust_proxy {
+            // The proxy appends the address it saw, anything before that came from the client
+            req.headers()
+                .get("x-forwarded-for")
+                .and_then(|forwarded_for| forwarded_for.to_str().ok())
+                .and_then(|forwarded_for| forwarded_for.rsplit(',').next())
+                .map(str::trim)
+                .filter(|forwarded| !forwarded.is_empty())
+                .map(String::from)
+                .or_else(|| req.peer_addr().map(|addr| addr.to_string()))
+        } else {
+            let peer_addr = req.peer_addr();
+            if peer_addr.is_some_and(|addr| addr.ip().is_loopback()) {
+                static WARNED: Once = Once::new();
+                WARNED.call_once(|| {
+                    println!(
+                        "Requests come from a local proxy but rate_limits.trust_proxy is off, \
+                         every client shares its IP's rate limits"
+                    )
+                });
+            }
+            peer_addr.map(|addr| addr.to_string())
+        }?;
+
+        // Peer addresses come with the port
+        Some(
+            addr.parse::<SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or(addr),
+        )
+    }
+
+    fn keys_for(&self, scope: &str, req: &ServiceRequest) -> Vec<String> {
+        if scope == USER {
+            return req
+                .extensions()
+                .get::<AuthEmail>()
+                .map(|auth_email| vec![format!("{}:{}", USER, auth_email.0.to_lowercase())])
+                .unwrap_or_default();
+        }
+
+        let ip = match self.client_ip(req) {
+            Some(ip) => ip,
+            None => return vec![],
+        };
+
+        let mut keys = vec![];
+        if LOGIN_PATHS.contains(&req.path()) {
+            keys.push(format!("{}:{}", LOGIN, ip));
+        }
+        keys.push(format!("{}:{}", IP, ip));
+        keys
+    }
+
+    fn load(&self, conn: &SqliteConnection) -> QueryResult<usize> {
+        use crate::schema::rate_limit_buckets::dsl::*;
+
+        let saved = rate_limit_buckets.load::<RateLimitBucket>(conn)?;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        for saved_bucket in saved.iter() {
+            if let Some(limit) = self.limit_for(&saved_bucket.bucket_key) {
+                buckets.insert(
+                    saved_bucket.bucket_key.clone(),
+                    Bucket {
+                        tokens: saved_bucket.tokens,
+                        refilled_at: saved_bucket.refilled_at,
+                        limit,
+                    },
+                );
+            }
+        }
+
+        Ok(buckets.len())
+    }
+
+    // Full buckets are the same as none, so only the others are kept
+    fn save(&self, conn: &SqliteConnection) -> QueryResult<usize> {
+        use crate::schema::rate_limit_buckets::dsl::*;
+
+        let now = now_seconds();
+        let rows = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+
+            buckets
+                .iter()
+                .map(|(key, bucket)| RateLimitBucket {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    bucket_key: key.clone(),
+                    tokens: bucket.tokens,
+                    refilled_at: bucket.refilled_at,
+                })
+                .collect::<Vec<RateLimitBucket>>()
+        };
+
+        conn.transaction(|| {
+            diesel::delete(rate_limit_buckets).execute(conn)?;
+            diesel::insert_into(rate_limit_buckets)
+                .values(&rows)
+                .execute(conn)
+        })
+    }
+}
+
+// Saves the buckets every so often when rate_limits.persist is on
+pub async fn persist_db(pool: DbPool, limiter: Arc<Limiter>) {
+    if !limiter.settings.persist {
+        return;
+    }
+
+    let loading = limiter.clone();
+    match pool.run(move |conn| loading.load(conn)).await {
+        Ok(loaded) => println!("Rate limit buckets loaded: {}", loaded),
+        Err(e) => println!("Error loading rate limit buckets: {:?}", e),
+    }
+
+    loop {
+        tokio::time::delay_for(std::time::Duration::from_secs(PERSIST_SECONDS)).await;
+
+        let saving = limiter.clone();
+        if let Err(e) = pool.run(move |conn| saving.save(conn)).await {
+            println!("Error saving rate limit buckets: {:?}", e);
+        }
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> Error {
+    InternalError::from_response(
+        "Too many requests",
+        HttpResponse::TooManyRequests()
+            .header("retry-after", retry_after.to_string())
+            .body("Too many requests"),
+    )
+    .into()
+}
+
+// Wrapped outside the auth middleware per IP, and inside it per user
+pub struct RateLimit {
+    limiter: Arc<Limiter>,
+    scope: &'static str,
+}
+
+impl RateLimit {
+    pub fn per_ip(limiter: Arc<Limiter>) -> RateLimit {
+        RateLimit { limiter, scope: IP }
+    }
+
+    pub fn per_user(limiter: Arc<Limiter>) -> RateLimit {
+        RateLimit {
+            limiter,
+            scope: USER,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service,
+            limiter: self.limiter.clone(),
+            scope: self.scope,
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<Limiter>,
+    scope: &'static str,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let now = now_seconds();
+
+        for key in self.limiter.keys_for(self.scope, &req) {
+            if let Err(retry_after) = self.limiter.take(&key, now) {
+                return Either::Right(err(too_many_requests(retry_after)));
+            }
+        }
+
+        Either::Left(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    fn limiter(burst: f64, per_minute: f64) -> Limiter {
+        let limit = Some(Limit { burst, per_minute });
+
+        Limiter::new(Settings {
+            ip: limit,
+            user: limit,
+            login: limit,
+            trust_proxy: false,
+            persist: false,
+        })
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter(2.0, 60.0);
+
+        assert_eq!(limiter.take("ip:1.2.3.4", 100.0), Ok(()));
+        assert_eq!(limiter.take("ip:1.2.3.4", 100.0), Ok(()));
+        assert_eq!(limiter.take("ip:1.2.3.4", 100.0), Err(1));
+        // Buckets are separate
+        assert_eq!(limiter.take("ip:5.6.7.8", 100.0), Ok(()));
+
+        assert_eq!(limiter.take("ip:1.2.3.4", 100.5), Err(1));
+        assert_eq!(limiter.take("ip:1.2.3.4", 101.0), Ok(()));
+        // Never more than the burst
+        assert_eq!(limiter.take("ip:1.2.3.4", 1000.0), Ok(()));
+        assert_eq!(limiter.take("ip:1.2.3.4", 1000.0), Ok(()));
+        assert_eq!(limiter.take("ip:1.2.3.4", 1000.0), Err(1));
+    }
+
+    #[test]
+    fn waits_for_slow_limits() {
+        let limiter = limiter(1.0, 2.0);
+
+        assert_eq!(limiter.take("user:a@x.com", 0.0), Ok(()));
+        assert_eq!(limiter.take("user:a@x.com", 0.0), Err(30));
+        assert_eq!(limiter.take("user:a@x.com", 15.0), Err(15));
+    }
+
+    #[actix_rt::test]
+    async fn answers_429_with_retry_after() {
+        let limiter = Arc::new(limiter(1.0, 6.0));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::per_ip(limiter))
+                .route("/currencies", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = || {
+            test::TestRequest::get()
+                .uri("/currencies")
+                .peer_addr("1.2.3.4:5555".parse().unwrap())
+                .to_request()
+        };
+
+        assert_eq!(app.call(req()).await.unwrap().status(), StatusCode::OK);
+
+        let limited = match app.call(req()).await {
+            Ok(_) => panic!("Not limited"),
+            Err(e) => e.as_response_error().error_response(),
+        };
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers().get("retry-after").unwrap(), "10");
+    }
+
+    #[test]
+    fn caps_the_buckets() {
+        // Never refilled, so none of them can be dropped for being full
+        let limiter = limiter(2.0, 0.0);
+
+        for i in 0..MAX_BUCKETS {
+            assert_eq!(limiter.take(&format!("ip:{}", i), i as f64), Ok(()));
+        }
+        assert_eq!(limiter.take("ip:new", MAX_BUCKETS as f64), Ok(()));
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() < MAX_BUCKETS);
+        // The least recently used went first
+        assert!(!buckets.contains_key("ip:0"));
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_BUCKETS - 1)));
+        assert!(buckets.contains_key("ip:new"));
+    }
+
+    #[test]
+    fn trusts_the_last_forwarded_address() {
+        let limiter = Limiter::new(Settings::default());
+
+        let req = test::TestRequest::get()
+            .uri("/currencies")
+            .peer_addr("10.0.0.1:5555".parse().unwrap())
+            .header("x-forwarded-for", "6.6.6.6, 1.2.3.4")
+            .to_srv_request();
+        assert_eq!(limiter.client_ip(&req).as_deref(), Some("1.2.3.4"));
+
+        let req = test::TestRequest::get()
+            .uri("/currencies")
+            .peer_addr("10.0.0.1:5555".parse().unwrap())
+            .to_srv_request();
+        assert_eq!(limiter.client_ip(&req).as_deref(), Some("10.0.0.1"));
+    }
+}
@@ -0,0 +1,372 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use tokio_diesel::*;
+
+use crate::currency::{self, ProviderUpdate};
+use crate::{budget, events, DbPool};
+
+// Polls Fixer for rates. Failed fetches are retried with exponential backoff and jitter before
+// waiting for the next poll, and the outcome is kept for GET /rates/status.
+
+const FIXER_URL: &str = "http://data.fixer.io/api/latest";
+
+// From rate_polling in env.json
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // How often to check whether rates are outdated
+    pub interval_minutes: u64,
+    // Retries after a failed fetch, before waiting for the next poll
+    pub retries: u32,
+    // First wait between retries, doubled each time up to max_backoff_seconds
+    pub backoff_seconds: u64,
+    pub max_backoff_seconds: u64,
+    pub timeout_seconds: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            interval_minutes: 60,
+            retries: 4,
+            backoff_seconds: 30,
+            max_backoff_seconds: 900,
+            timeout_seconds: 30,
+        }
+    }
+}
+
+impl Settings {
+    // Wait before retry number attempt, between half and all of the doubled backoff so
+    // instances don't retry in step. random is 0 to 1.
+    fn backoff(&self, attempt: u32, random: f64) -> std::time::Duration {
+        let doubled = self
+            .backoff_seconds
+            .saturating_mul(2_u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff_seconds) as f64;
+
+        std::time::Duration::from_secs_f64(doubled / 2.0 + doubled / 2.0 * random.clamp(0.0, 1.0))
+    }
+}
+
+#[derive(Debug)]
+pub struct FetchError {
+    pub message: String,
+    // Provider errors like a bad key won't go away by asking again
+    pub retry: bool,
+}
+
+impl FetchError {
+    fn retry(message: String) -> FetchError {
+        FetchError {
+            message,
+            retry: true,
+        }
+    }
+}
+
+// The last refresh, for admins to see why rates are stale
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    pub last_attempt_at: Option<NaiveDateTime>,
+    pub last_success_at: Option<NaiveDateTime>,
+    // Cleared by the next successful refresh
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub updated: usize,
+    pub added: Vec<String>,
+    pub retired: Vec<String>,
+    pub rejected: Vec<String>,
+}
+
+fn status_lock() -> &'static Mutex<Status> {
+    static STATUS: OnceLock<Mutex<Status>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(Status::default()))
+}
+
+pub fn status() -> Status {
+    status_lock()
+        .lock()
+        .map(|status| status.clone())
+        .unwrap_or_default()
+}
+
+fn set_status(change: impl FnOnce(&mut Status)) {
+    if let Ok(mut status) = status_lock().lock() {
+        change(&mut status);
+    }
+}
+
+fn failed(message: &str) {
+    println!("Rate refresh failed: {}", message);
+
+    set_status(|status| {
+        status.last_attempt_at = Some(Utc::now().naive_utc());
+        status.last_error = Some(message.to_string());
+        status.consecutive_failures += 1;
+    });
+}
+
+fn today() -> NaiveDateTime {
+    let utc_now = Utc::now();
+    NaiveDate::from_ymd(utc_now.year(), utc_now.month(), utc_now.day()).and_hms(0, 0, 0)
+}
+
+// The rates in a Fixer response. Codes sent without a number are kept as NaN so they are
+// rejected rather than treated as no longer provided.
+pub fn parse_rates(resp_text: &str) -> Result<BTreeMap<String, f64>, FetchError> {
+    let resp_json: Value = serde_json::from_str(resp_text)
+        .map_err(|e| FetchError::retry(format!("Invalid JSON from Fixer: {}", e)))?;
+
+    if resp_json["success"] == Value::Bool(false) {
+        let error = &resp_json["error"];
+        return Err(FetchError {
+            message: format!(
+                "Fixer error {}: {}",
+                error["code"],
+                error["info"]
+                    .as_str()
+                    .or_else(|| error["type"].as_str())
+                    .unwrap_or("unknown")
+            ),
+            retry: false,
+        });
+    }
+
+    let rates: BTreeMap<String, f64> = resp_json["rates"]
+        .as_object()
+        .map(|rates| {
+            rates
+                .iter()
+                .map(|(code, code_rate)| (code.clone(), code_rate.as_f64().unwrap_or(f64::NAN)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if rates.is_empty() {
+        return Err(FetchError::retry(String::from("No rates from Fixer")));
+    }
+
+    Ok(rates)
+}
+
+// Without the url, which has the API key in it
+fn describe(e: &reqwest::Error) -> String {
+    match std::error::Error::source(e) {
+        Some(source) => source.to_string(),
+        None if e.is_timeout() => String::from("timed out"),
+        None => String::from("request failed"),
+    }
+}
+
+async fn fetch(
+    settings: &Settings,
+    fixer_api_key: &str,
+) -> Result<BTreeMap<String, f64>, FetchError> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(settings.timeout_seconds))
+        .build()
+        .map_err(|e| FetchError::retry(format!("Error building client: {}", e)))?;
+
+    let resp = client
+        .get(FIXER_URL)
+        .query(&[("access_key", fixer_api_key)])
+        .send()
+        .await
+        .map_err(|e| FetchError::retry(format!("Error fetching rates: {}", describe(&e))))?;
+
+    let resp_status = resp.status();
+    let resp_text = resp
+        .text()
+        .await
+        .map_err(|e| FetchError::retry(format!("Error reading rates: {}", describe(&e))))?;
+
+    if !resp_status.is_success() {
+        return Err(FetchError::retry(format!(
+            "Fixer responded {}",
+            resp_status
+        )));
+    }
+
+    parse_rates(&resp_text)
+}
+
+// Fetches and stores the latest rates, retrying failed fetches
+pub async fn refresh_rates(
+    pool: &DbPool,
+    fixer_api_key: &str,
+    settings: &Settings,
+) -> Result<ProviderUpdate, String> {
+    if fixer_api_key.is_empty() {
+        let message = "fixer_api_key is not set in env.json";
+        failed(message);
+        return Err(message.to_string());
+    }
+
+    let mut attempt = 0;
+    let rates = loop {
+        match fetch(settings, fixer_api_key).await {
+            Ok(rates) => break rates,
+            Err(e) if e.retry && attempt < settings.retries => {
+                attempt += 1;
+                let wait = settings.backoff(attempt, OsRng.next_u32() as f64 / u32::MAX as f64);
+
+                failed(&e.message);
+                set_status(|status| {
+                    status.next_attempt_at = Some(
+                        Utc::now().naive_utc() + Duration::milliseconds(wait.as_millis() as i64),
+                    )
+                });
+                println!(
+                    "Retrying in {}s ({} of {})",
+                    wait.as_secs(),
+                    attempt,
+                    settings.retries
+                );
+
+                tokio::time::delay_for(wait).await;
+            }
+            Err(e) => {
+                failed(&e.message);
+                return Err(e.message);
+            }
+        }
+    };
+
+    let day = today();
+    let update = match pool
+        .run(move |conn| currency::apply_provider_rates(conn, &rates, day))
+        .await
+    {
+        Ok(update) => update,
+        Err(e) => {
+            let message = format!("Error updating currencies: {:?}", e);
+            failed(&message);
+            return Err(message);
+        }
+    };
+
+    println!("Updated {} currencies", update.updated);
+    if !update.added.is_empty() {
+        println!("New currencies: {}", update.added.join(", "));
+    }
+    if !update.retired.is_empty() {
+        println!(
+            "No longer provided, now inactive: {}",
+            update.retired.join(", ")
+        );
+    }
+    if !update.rejected.is_empty() {
+        println!(
+            "Invalid rates, keeping the last ones: {}",
+            update.rejected.join(", ")
+        );
+    }
+
+    set_status(|status| {
+        let now = Utc::now().naive_utc();
+        status.last_attempt_at = Some(now);
+        status.last_success_at = Some(now);
+        status.last_error = None;
+        status.consecutive_failures = 0;
+        status.updated = update.updated;
+        status.added = update.added.clone();
+        status.retired = update.retired.clone();
+        status.rejected = update.rejected.clone();
+    });
+
+    budget::rates_changed(pool).await;
+    events::publish(None, events::RATES_REFRESHED, Value::Null);
+
+    Ok(update)
+}
+
+// How many active provider currencies are not updated today
+pub async fn count_outdated_currencies(pool: &DbPool) -> i32 {
+    use crate::schema::currencies::dsl::*;
+
+    let outdated = currencies
+        .filter(active.eq(true))
+        .filter(custom.eq(false))
+        .filter(last_update_day.is_null().or(last_update_day.lt(today())))
+        .count()
+        .get_result_async::<i64>(pool)
+        .await;
+
+    match outdated {
+        Ok(outdated) => outdated as i32,
+        Err(e) => {
+            println!("Error counting outdated currencies: {:?}", e);
+            0
+        }
+    }
+}
+
+pub async fn poll_db(pool: DbPool, fixer_api_key: String, settings: Settings) {
+    let interval = std::time::Duration::from_secs(settings.interval_minutes.max(1) * 60);
+
+    loop {
+        let un_updated_val = count_outdated_currencies(&pool).await;
+
+        println!("Currencies not updated: {}", un_updated_val);
+
+        if un_updated_val > 0 {
+            // Failures are logged and kept in the status, the next poll tries again
+            let _ = refresh_rates(&pool, &fixer_api_key, &settings).await;
+        } else {
+            println!("No need to update. Already latest.");
+        }
+
+        set_status(|status| {
+            status.next_attempt_at =
+                Some(Utc::now().naive_utc() + Duration::seconds(interval.as_secs() as i64))
+        });
+
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_with_jitter() {
+        let settings = Settings::default();
+
+        assert_eq!(settings.backoff(1, 1.0).as_secs(), 30);
+        assert_eq!(settings.backoff(1, 0.0).as_secs(), 15);
+        assert_eq!(settings.backoff(3, 1.0).as_secs(), 120);
+        // Capped
+        assert_eq!(settings.backoff(20, 1.0).as_secs(), 900);
+        assert_eq!(settings.backoff(200, 0.0).as_secs(), 450);
+    }
+
+    #[test]
+    fn checks_fixer_responses() {
+        let failed = parse_rates(
+            r#"{"success": false, "error": {"code": 101, "type": "invalid_access_key", "info": "You have not supplied a valid API Access Key."}}"#,
+        )
+        .unwrap_err();
+        assert!(!failed.retry);
+        assert!(failed.message.contains("101"));
+
+        assert!(parse_rates("<html>").unwrap_err().retry);
+        assert!(
+            parse_rates(r#"{"success": true, "rates": {}}"#)
+                .unwrap_err()
+                .retry
+        );
+
+        let rates =
+            parse_rates(r#"{"success": true, "rates": {"EUR": 1, "USD": 1.1, "XXX": null}}"#)
+                .unwrap();
+        assert_eq!(rates["EUR"], 1.0);
+        assert!(rates["XXX"].is_nan());
+    }
+}
@@ -1,11 +1,85 @@
+table! {
+    api_keys (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        name -> Text,
+        prefix -> Text,
+        key_hash -> Text,
+        read_only -> Bool,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    audit_logs (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Nullable<Integer>,
+        actor -> Text,
+        action -> Text,
+        entity -> Text,
+        entity_id -> Nullable<Integer>,
+        before_json -> Nullable<Text>,
+        after_json -> Nullable<Text>,
+        request_id -> Nullable<Text>,
+    }
+}
+
+table! {
+    budget_alerts (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        budget_id -> Integer,
+        email_id -> Integer,
+        monthly_limit -> Float,
+        spent -> Float,
+        is_over -> Bool,
+        source -> Text,
+    }
+}
+
+table! {
+    budgets (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        category_id -> Nullable<Integer>,
+        monthly_limit -> Float,
+        is_over -> Bool,
+    }
+}
+
+table! {
+    categories (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        name -> Text,
+    }
+}
+
 table! {
     currencies (id) {
         id -> Nullable<Integer>,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
-        name -> Nullable<Text>,
-        rate -> Nullable<Float>,
+        name -> Text,
+        rate -> Float,
         last_update_day -> Nullable<Timestamp>,
+        manual_rate -> Nullable<Float>,
+        provider_rate -> Nullable<Float>,
+        custom -> Bool,
+        display_name -> Nullable<Text>,
+        symbol -> Nullable<Text>,
+        minor_units -> Integer,
+        active -> Bool,
     }
 }
 
@@ -14,8 +88,41 @@ table! {
         id -> Nullable<Integer>,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
-        email -> Nullable<Text>,
-        currency_id -> Nullable<Integer>,
+        email -> Text,
+        currencie_id -> Nullable<Integer>,
+        version -> Integer,
+    }
+}
+
+table! {
+    household_invites (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        household_id -> Integer,
+        email -> Text,
+        role -> Text,
+        invited_by -> Nullable<Integer>,
+    }
+}
+
+table! {
+    household_members (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        household_id -> Integer,
+        email_id -> Integer,
+        role -> Text,
+    }
+}
+
+table! {
+    households (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        name -> Text,
         currencie_id -> Nullable<Integer>,
     }
 }
@@ -25,8 +132,8 @@ table! {
         id -> Nullable<Integer>,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
-        name -> Nullable<Text>,
-        modifier -> Nullable<Float>,
+        name -> Text,
+        modifier -> Float,
     }
 }
 
@@ -40,24 +147,208 @@ table! {
     }
 }
 
+table! {
+    local_accounts (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        password_hash -> Text,
+    }
+}
+
+table! {
+    rate_limit_buckets (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        bucket_key -> Text,
+        tokens -> Double,
+        refilled_at -> Double,
+    }
+}
+
+table! {
+    rate_overrides (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        currencie_id -> Integer,
+        rate -> Float,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email -> Text,
+        refresh_token_hash -> Text,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    spending_snapshots (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        currencie_id -> Nullable<Integer>,
+        snapshot_day -> Timestamp,
+        monthly_cost -> Float,
+    }
+}
+
+table! {
+    subscription_prices (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        subscription_id -> Integer,
+        cost -> Float,
+        currencie_id -> Nullable<Integer>,
+        interval_id -> Nullable<Integer>,
+        interval_amount -> Integer,
+        effective_from -> Timestamp,
+        effective_to -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    subscription_shares (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        subscription_id -> Integer,
+        email_id -> Integer,
+        ratio -> Nullable<Float>,
+        fixed_amount -> Nullable<Float>,
+    }
+}
+
 table! {
     subscriptions (id) {
         id -> Nullable<Integer>,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
-        email_id -> Nullable<Integer>,
-        name -> Nullable<Text>,
-        cost -> Nullable<Float>,
+        email_id -> Integer,
+        name -> Text,
+        cost -> Float,
         interval_id -> Nullable<Integer>,
-        interval_amount -> Nullable<Integer>,
+        interval_amount -> Integer,
         currencie_id -> Nullable<Integer>,
+        category_id -> Nullable<Integer>,
+        trial_ends_at -> Nullable<Timestamp>,
+        promo_cost -> Nullable<Float>,
+        promo_months -> Nullable<Integer>,
+        status -> Text,
+        paused_until -> Nullable<Timestamp>,
+        cancelled_at -> Nullable<Timestamp>,
+        status_changed_at -> Nullable<Timestamp>,
+        household_id -> Nullable<Integer>,
+        version -> Integer,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        subscription_id -> Integer,
+        name -> Text,
     }
 }
 
+table! {
+    webhook_deliveries (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        webhook_id -> Integer,
+        event_type -> Text,
+        event_key -> Nullable<Text>,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        next_attempt_at -> Nullable<Timestamp>,
+        response_status -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Nullable<Integer>,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        email_id -> Integer,
+        url -> Text,
+        secret -> Text,
+        event_types -> Text,
+        active -> Bool,
+    }
+}
+
+joinable!(api_keys -> emails (email_id));
+joinable!(budget_alerts -> budgets (budget_id));
+joinable!(budget_alerts -> emails (email_id));
+joinable!(budgets -> categories (category_id));
+joinable!(budgets -> emails (email_id));
+joinable!(categories -> emails (email_id));
+joinable!(emails -> currencies (currencie_id));
+joinable!(household_invites -> emails (invited_by));
+joinable!(household_invites -> households (household_id));
+joinable!(household_members -> emails (email_id));
+joinable!(household_members -> households (household_id));
+joinable!(households -> currencies (currencie_id));
+joinable!(local_accounts -> emails (email_id));
+joinable!(rate_overrides -> currencies (currencie_id));
+joinable!(rate_overrides -> emails (email_id));
+joinable!(spending_snapshots -> currencies (currencie_id));
+joinable!(spending_snapshots -> emails (email_id));
+joinable!(subscription_prices -> currencies (currencie_id));
+joinable!(subscription_prices -> intervals (interval_id));
+joinable!(subscription_prices -> subscriptions (subscription_id));
+joinable!(subscription_shares -> emails (email_id));
+joinable!(subscription_shares -> subscriptions (subscription_id));
+joinable!(subscriptions -> categories (category_id));
+joinable!(subscriptions -> currencies (currencie_id));
+joinable!(subscriptions -> emails (email_id));
+joinable!(subscriptions -> households (household_id));
+joinable!(subscriptions -> intervals (interval_id));
+joinable!(tags -> subscriptions (subscription_id));
+
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(webhooks -> emails (email_id));
+
 allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_logs,
+    budget_alerts,
+    budgets,
+    categories,
     currencies,
     emails,
+    household_invites,
+    household_members,
+    households,
     intervals,
     intervals_subscriptions,
+    local_accounts,
+    rate_limit_buckets,
+    rate_overrides,
+    sessions,
+    spending_snapshots,
+    subscription_prices,
+    subscription_shares,
     subscriptions,
+    tags,
+    webhook_deliveries,
+    webhooks,
 );
@@ -0,0 +1,283 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use sha2::{Digest, Sha256};
+
+use crate::model::Session;
+
+// Every login starts a session. Its access token is a short lived JWT signed with the
+// session_secret and sent like any id token, while the refresh token gets new ones until the
+// session expires or is revoked. Only a hash of the refresh token is stored.
+
+// The iss of tokens we sign ourselves
+pub const ISSUER: &str = "monty";
+
+const INVALID_REFRESH: &str = "Invalid refresh token";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lifetimes {
+    pub access: Duration,
+    // Extended on every refresh
+    pub refresh: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccessClaims {
+    iss: String,
+    sub: String,
+    sid: i32,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionTokens {
+    pub email: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    // Seconds until the access token expires
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshBody {
+    pub refresh_token: String,
+}
+
+fn new_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn session_tokens(
+    secret: &[u8],
+    lifetimes: Lifetimes,
+    email: &str,
+    session_id: i32,
+    refresh_token: String,
+) -> Result<SessionTokens, String> {
+    let now = Utc::now().timestamp();
+    let expires_in = lifetimes.access.num_seconds();
+
+    let claims = AccessClaims {
+        iss: String::from(ISSUER),
+        sub: email.to_string(),
+        sid: session_id,
+        iat: now,
+        exp: now + expires_in,
+    };
+
+    let access_token = encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(SessionTokens {
+        email: email.to_string(),
+        access_token,
+        refresh_token,
+        expires_in,
+    })
+}
+
+pub fn start(
+    conn: &SqliteConnection,
+    secret: &[u8],
+    lifetimes: Lifetimes,
+    address: &str,
+) -> Result<SessionTokens, String> {
+    use crate::schema::sessions::dsl::*;
+
+    let refresh_token = new_refresh_token();
+    let token_hash = hash_token(&refresh_token);
+
+    let session_id = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            diesel::insert_into(sessions)
+                .values(Session {
+                    id: None,
+                    created_at: None,
+                    updated_at: None,
+                    email: address.to_string(),
+                    refresh_token_hash: token_hash.clone(),
+                    expires_at: (Utc::now() + lifetimes.refresh).naive_utc(),
+                    revoked_at: None,
+                })
+                .execute(conn)?;
+
+            sessions
+                .filter(refresh_token_hash.eq(&token_hash))
+                .select(id)
+                .first::<Option<i32>>(conn)
+        })
+        .map_err(|e| e.to_string())?
+        .unwrap_or(0);
+
+    session_tokens(secret, lifetimes, address, session_id, refresh_token)
+}
+
+// New tokens for a live session. The refresh token is replaced, so each one works once.
+pub fn refresh(
+    conn: &SqliteConnection,
+    secret: &[u8],
+    lifetimes: Lifetimes,
+    refresh_token: &str,
+) -> Result<SessionTokens, String> {
+    use crate::schema::sessions::dsl::*;
+
+    let now = Utc::now().naive_utc();
+    let new_token = new_refresh_token();
+
+    let found = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let found = sessions
+                .filter(refresh_token_hash.eq(hash_token(refresh_token)))
+                .filter(revoked_at.is_null())
+                .filter(expires_at.gt(now))
+                .first::<Session>(conn)
+                .optional()?;
+
+            if let Some(found) = &found {
+                diesel::update(sessions.filter(id.eq(found.id)))
+                    .set((
+                        refresh_token_hash.eq(hash_token(&new_token)),
+                        expires_at.eq((Utc::now() + lifetimes.refresh).naive_utc()),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(found)
+        })
+        .map_err(|e| e.to_string())?;
+
+    match found {
+        Some(found) => session_tokens(
+            secret,
+            lifetimes,
+            &found.email,
+            found.id.unwrap_or(0),
+            new_token,
+        ),
+        None => Err(String::from(INVALID_REFRESH)),
+    }
+}
+
+pub fn revoke(conn: &SqliteConnection, session_id: i32) -> QueryResult<usize> {
+    use crate::schema::sessions::dsl::*;
+
+    diesel::update(
+        sessions
+            .filter(id.eq(session_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+// Signs the email out everywhere
+pub fn revoke_all(conn: &SqliteConnection, address: &str) -> QueryResult<usize> {
+    use crate::schema::sessions::dsl::*;
+
+    diesel::update(
+        sessions
+            .filter(email.eq(address))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+pub fn is_active(conn: &SqliteConnection, session_id: i32) -> QueryResult<bool> {
+    use crate::schema::sessions::dsl::*;
+
+    sessions
+        .filter(id.eq(session_id))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.gt(Utc::now().naive_utc()))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count > 0)
+}
+
+// The email and session of an access token we signed, if it hasn't expired. Whether the
+// session was revoked is up to the caller, see is_active.
+pub fn verify_access_token(secret: &[u8], token: &str) -> Option<(String, i32)> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[ISSUER]);
+
+    decode::<AccessClaims>(token, &DecodingKey::from_secret(secret), &validation)
+        .map(|data| (data.claims.sub, data.claims.sid))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{establish_pool, run_migrations};
+
+    const SECRET: &[u8] = b"secret";
+
+    fn lifetimes() -> Lifetimes {
+        Lifetimes {
+            access: Duration::minutes(5),
+            refresh: Duration::days(1),
+        }
+    }
+
+    #[test]
+    fn refreshes_and_revokes() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+
+        let started = start(&conn, SECRET, lifetimes(), "a@x.com").unwrap();
+        let (address, session_id) = verify_access_token(SECRET, &started.access_token).unwrap();
+        assert_eq!(address, "a@x.com");
+        assert!(is_active(&conn, session_id).unwrap());
+        assert_eq!(
+            verify_access_token(b"other secret", &started.access_token),
+            None
+        );
+
+        // Refresh tokens work once
+        let refreshed = refresh(&conn, SECRET, lifetimes(), &started.refresh_token).unwrap();
+        assert!(refresh(&conn, SECRET, lifetimes(), &started.refresh_token).is_err());
+        assert_eq!(
+            verify_access_token(SECRET, &refreshed.access_token),
+            Some((String::from("a@x.com"), session_id))
+        );
+
+        revoke(&conn, session_id).unwrap();
+        assert!(!is_active(&conn, session_id).unwrap());
+        assert!(refresh(&conn, SECRET, lifetimes(), &refreshed.refresh_token).is_err());
+    }
+
+    #[test]
+    fn revokes_every_session() {
+        let pool = establish_pool(":memory:");
+        run_migrations(&pool);
+        let conn = pool.get().unwrap();
+
+        let first = start(&conn, SECRET, lifetimes(), "a@x.com").unwrap();
+        let second = start(&conn, SECRET, lifetimes(), "a@x.com").unwrap();
+        let other = start(&conn, SECRET, lifetimes(), "b@x.com").unwrap();
+
+        assert_eq!(revoke_all(&conn, "a@x.com").unwrap(), 2);
+
+        let session_of = |tokens: &SessionTokens| {
+            verify_access_token(SECRET, &tokens.access_token)
+                .map(|(_, session_id)| is_active(&conn, session_id).unwrap())
+        };
+        assert_eq!(session_of(&first), Some(false));
+        assert_eq!(session_of(&second), Some(false));
+        assert_eq!(session_of(&other), Some(true));
+    }
+}
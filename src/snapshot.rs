@@ -0,0 +1,129 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::time::Duration;
+use tokio_diesel::*;
+
+use crate::cost::CostContext;
+use crate::model::{Email, SpendingSnapshot};
+use crate::DbPool;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotInterval {
+    Daily,
+    Monthly,
+}
+
+impl SnapshotInterval {
+    pub fn from_config(snapshot_interval: &str) -> SnapshotInterval {
+        match snapshot_interval {
+            "monthly" => SnapshotInterval::Monthly,
+            _ => SnapshotInterval::Daily,
+        }
+    }
+
+    // The day a snapshot taken now is recorded under
+    pub fn snapshot_day(&self) -> NaiveDateTime {
+        let utc_now = Utc::now();
+        let day = match self {
+            SnapshotInterval::Daily => utc_now.day(),
+            SnapshotInterval::Monthly => 1,
+        };
+
+        NaiveDate::from_ymd(utc_now.year(), utc_now.month(), day).and_hms(0, 0, 0)
+    }
+}
+
+// Records every email's monthly cost for the current period, once per period
+pub fn take_snapshots(
+    conn: &SqliteConnection,
+    snapshot_interval: SnapshotInterval,
+) -> QueryResult<usize> {
+    let snapshot_day = snapshot_interval.snapshot_day();
+
+    let emails_list = {
+        use crate::schema::emails::dsl::*;
+        emails.load::<Email>(conn)?
+    };
+
+    let mut taken = 0;
+
+    for email in emails_list {
+        let email_id = match email.id {
+            Some(email_id) => email_id,
+            None => continue,
+        };
+        let context = CostContext::load(conn, email)?;
+
+        use crate::schema::spending_snapshots::dsl::spending_snapshots;
+
+        taken += diesel::insert_or_ignore_into(spending_snapshots)
+            .values(&SpendingSnapshot {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                email_id,
+                currencie_id: context.email.currencie_id,
+                snapshot_day,
+                monthly_cost: context.total().monthly,
+            })
+            .execute(conn)?;
+    }
+
+    Ok(taken)
+}
+
+pub async fn snapshot_db(pool: DbPool, snapshot_interval: SnapshotInterval) {
+    loop {
+        match pool
+            .run(move |conn| take_snapshots(conn, snapshot_interval))
+            .await
+        {
+            Ok(taken) => println!("Spending snapshots taken: {}", taken),
+            Err(e) => println!("Error taking spending snapshots: {:?}", e),
+        }
+
+        tokio::time::delay_for(Duration::from_secs(3600)).await;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryPoint {
+    pub snapshot_day: NaiveDateTime,
+    pub currencie_id: Option<i32>,
+    pub monthly_cost: f32,
+    pub change: Option<f32>,
+    pub change_percent: Option<f32>,
+}
+
+// Snapshots in order, each with its change from the previous one
+pub fn history(snapshots: Vec<SpendingSnapshot>) -> Vec<HistoryPoint> {
+    let mut previous: Option<SpendingSnapshot> = None;
+
+    snapshots
+        .into_iter()
+        .map(|snapshot| {
+            // Costs in different currencies can't be compared
+            let comparable = previous
+                .as_ref()
+                .filter(|previous| previous.currencie_id == snapshot.currencie_id);
+
+            let change = comparable.map(|previous| snapshot.monthly_cost - previous.monthly_cost);
+            let change_percent = comparable
+                .filter(|previous| previous.monthly_cost > 0.0)
+                .map(|previous| {
+                    (snapshot.monthly_cost - previous.monthly_cost) / previous.monthly_cost * 100.0
+                });
+
+            let point = HistoryPoint {
+                snapshot_day: snapshot.snapshot_day,
+                currencie_id: snapshot.currencie_id,
+                monthly_cost: snapshot.monthly_cost,
+                change,
+                change_percent,
+            };
+
+            previous = Some(snapshot);
+            point
+        })
+        .collect()
+}
@@ -0,0 +1,83 @@
+use actix_web::{
+    dev::Payload, error::ErrorPreconditionRequired, Error, FromRequest, HttpRequest, HttpResponse,
+};
+use futures::future::{err, ok, Ready};
+use serde::Serialize;
+
+// Emails and subscriptions carry a version that every update bumps (see the add_versions
+// migration). It's sent as the ETag, and mutations must send it back as If-Match so a stale
+// tab can't overwrite changes it hasn't seen.
+
+const REQUIRED: &str = "If-Match header required";
+
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+// The versions an If-Match header accepts, or any version for `*`
+#[derive(Debug, Clone)]
+pub struct IfMatch(Option<Vec<i32>>);
+
+impl IfMatch {
+    pub fn parse(header: &str) -> Option<IfMatch> {
+        if header.trim() == "*" {
+            return Some(IfMatch(None));
+        }
+
+        let versions = header
+            .split(',')
+            .map(|tag| {
+                tag.trim()
+                    .trim_start_matches("W/")
+                    .trim_matches('"')
+                    .parse::<i32>()
+            })
+            .collect::<Result<Vec<i32>, _>>()
+            .ok()?;
+
+        Some(IfMatch(Some(versions)))
+    }
+
+    pub fn matches(&self, version: i32) -> bool {
+        match &self.0 {
+            Some(versions) => versions.contains(&version),
+            None => true,
+        }
+    }
+}
+
+impl FromRequest for IfMatch {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req
+            .headers()
+            .get("if-match")
+            .and_then(|header| header.to_str().ok())
+            .and_then(IfMatch::parse)
+        {
+            Some(if_match) => ok(if_match),
+            None => err(ErrorPreconditionRequired(REQUIRED)),
+        }
+    }
+}
+
+// What a versioned change came to, the conflict carrying the current state
+pub enum Outcome<T, C = T> {
+    Saved(T),
+    Conflict(C),
+}
+
+// 412 with the current state and its version, for the client to merge and retry
+pub fn conflict<T: Serialize>(version: i32, current: &T) -> HttpResponse {
+    HttpResponse::PreconditionFailed()
+        .header("etag", etag(version))
+        .json(current)
+}
+
+// 428 for updates sent without If-Match
+pub fn required() -> HttpResponse {
+    HttpResponse::PreconditionRequired().body(REQUIRED)
+}
@@ -0,0 +1,406 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::net::IpAddr;
+use tokio_diesel::*;
+
+use crate::model::{Email, Webhook, WebhookDelivery};
+use crate::{cost, DbPool};
+
+pub const SUBSCRIPTION_CREATED: &str = "subscription.created";
+pub const SUBSCRIPTION_UPDATED: &str = "subscription.updated";
+pub const SUBSCRIPTION_DELETED: &str = "subscription.deleted";
+pub const SUBSCRIPTION_RENEWAL_DUE: &str = "subscription.renewal_due";
+pub const BUDGET_CROSSED: &str = "budget.crossed";
+
+pub const EVENT_TYPES: [&str; 5] = [
+    SUBSCRIPTION_CREATED,
+    SUBSCRIPTION_UPDATED,
+    SUBSCRIPTION_DELETED,
+    SUBSCRIPTION_RENEWAL_DUE,
+    BUDGET_CROSSED,
+];
+
+const PENDING: &str = "pending";
+const DELIVERED: &str = "delivered";
+const FAILED: &str = "failed";
+
+// Retries wait 30s, 1m, 2m, 4m and 8m before giving up
+const MAX_ATTEMPTS: i32 = 6;
+const BACKOFF_SECONDS: i64 = 30;
+
+// How far ahead renewal_due is sent
+const RENEWAL_NOTICE_DAYS: i64 = 3;
+
+pub fn subscribes_to(webhook: &Webhook, event_type: &str) -> bool {
+    webhook.active
+        && (webhook.event_types.trim().is_empty()
+            || webhook
+                .event_types
+                .split(',')
+                .any(|filter| filter.trim() == event_type))
+}
+
+// Unknown names in a comma separated event filter
+pub fn unknown_event_types(event_types: &str) -> Vec<String> {
+    event_types
+        .split(',')
+        .map(|filter| filter.trim())
+        .filter(|filter| !filter.is_empty() && !EVENT_TYPES.contains(filter))
+        .map(String::from)
+        .collect()
+}
+
+// Sent as X-Monty-Signature, receivers recompute it over the raw body with their secret
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(payload.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Addresses on the server's own network, which webhooks must not reach
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || ip.segments()[0] & 0xfe00 == 0xfc00
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+// Checked when a webhook is saved and again before every delivery, as the host may resolve
+// elsewhere by then
+pub async fn check_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid url: {}", e))?;
+
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(String::from("url must be http or https"));
+    }
+
+    let host = parsed
+        .host_str()
+        .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        .ok_or_else(|| String::from("url must have a host"))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Can't resolve {}: {}", host, e))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(format!("Can't resolve {}", host));
+    }
+    if addrs.iter().any(|addr| is_internal(addr.ip())) {
+        return Err(format!("{} is a private address", host));
+    }
+
+    Ok(())
+}
+
+fn backoff(attempts: i32) -> Duration {
+    Duration::seconds(BACKOFF_SECONDS * 2_i64.pow((attempts - 1).max(0) as u32))
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    created_at: NaiveDateTime,
+    data: &'a Value,
+}
+
+// Queues the event for every active webhook of these emails that asked for it
+pub fn emit(
+    conn: &SqliteConnection,
+    email_ids: Vec<i32>,
+    event_type: &str,
+    event_key: Option<String>,
+    data: Value,
+) -> QueryResult<usize> {
+    let webhooks_list = {
+        use crate::schema::webhooks::dsl::*;
+        webhooks
+            .filter(email_id.eq_any(email_ids))
+            .load::<Webhook>(conn)?
+    };
+
+    let now = Utc::now().naive_utc();
+    let payload = serde_json::to_string(&Event {
+        event: event_type,
+        created_at: now,
+        data: &data,
+    })
+    .unwrap_or_default();
+
+    let deliveries: Vec<WebhookDelivery> = webhooks_list
+        .iter()
+        .filter(|webhook| subscribes_to(webhook, event_type))
+        .filter_map(|webhook| {
+            Some(WebhookDelivery {
+                id: None,
+                created_at: None,
+                updated_at: None,
+                webhook_id: webhook.id?,
+                event_type: event_type.to_string(),
+                event_key: event_key.clone(),
+                payload: payload.clone(),
+                status: String::from(PENDING),
+                attempts: 0,
+                next_attempt_at: Some(now),
+                response_status: None,
+                last_error: None,
+                delivered_at: None,
+            })
+        })
+        .collect();
+
+    use crate::schema::webhook_deliveries::dsl::webhook_deliveries;
+    diesel::insert_or_ignore_into(webhook_deliveries)
+        .values(&deliveries)
+        .execute(conn)
+}
+
+pub fn delete_webhook(conn: &SqliteConnection, webhook_id: i32) -> QueryResult<usize> {
+    {
+        use crate::schema::webhook_deliveries::dsl;
+        diesel::delete(dsl::webhook_deliveries.filter(dsl::webhook_id.eq(webhook_id)))
+            .execute(conn)?;
+    }
+
+    use crate::schema::webhooks::dsl::webhooks;
+    diesel::delete(webhooks.find(webhook_id)).execute(conn)
+}
+
+// Queues renewal_due for charges in the next few days, once per charge
+pub fn queue_renewals(conn: &SqliteConnection) -> QueryResult<usize> {
+    let emails_list = {
+        use crate::schema::webhooks::dsl;
+        let email_ids = dsl::webhooks
+            .filter(dsl::active.eq(true))
+            .select(dsl::email_id)
+            .distinct()
+            .load::<i32>(conn)?;
+
+        use crate::schema::emails::dsl::*;
+        emails
+            .filter(id.eq_any(email_ids.into_iter().map(Some)))
+            .load::<Email>(conn)?
+    };
+
+    let mut queued = 0;
+
+    for email in emails_list {
+        let email_id = match email.id {
+            Some(email_id) => email_id,
+            None => continue,
+        };
+
+        for upcoming_charge in cost::upcoming(conn, email, RENEWAL_NOTICE_DAYS)?.charges {
+            let event_key = format!(
+                "renewal:{}:{}",
+                upcoming_charge.subscription_id.unwrap_or(0),
+                upcoming_charge.charge.day
+            );
+
+            queued += emit(
+                conn,
+                vec![email_id],
+                SUBSCRIPTION_RENEWAL_DUE,
+                Some(event_key),
+                serde_json::to_value(&upcoming_charge).unwrap_or_default(),
+            )?;
+        }
+    }
+
+    Ok(queued)
+}
+
+// Sends one delivery, returning the response status
+async fn send(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> Result<u16, String> {
+    check_url(&webhook.url).await?;
+
+    let resp = client
+        .post(webhook.url.as_str())
+        .header("content-type", "application/json")
+        .header("x-monty-event", delivery.event_type.as_str())
+        .header("x-monty-delivery", delivery.id.unwrap_or(0).to_string())
+        .header(
+            "x-monty-signature",
+            sign(&webhook.secret, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // reqwest resolves the host again to connect, so a rebinding host can pass check_url and
+    // then land on an internal address. The request has gone out by then, but the response is
+    // dropped and the delivery fails.
+    if let Some(addr) = resp.remote_addr().filter(|addr| is_internal(addr.ip())) {
+        return Err(format!("Connected to private address {}", addr.ip()));
+    }
+
+    let status = resp.status();
+
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err(format!("HTTP {}", status.as_u16()))
+    }
+}
+
+// Sends every delivery that's due, rescheduling failures with exponential backoff
+pub async fn deliver_pending(pool: &DbPool, client: &reqwest::Client) -> usize {
+    let now = Utc::now().naive_utc();
+
+    let due = pool
+        .run(move |conn| {
+            use crate::schema::webhook_deliveries::dsl;
+            use crate::schema::webhooks;
+
+            dsl::webhook_deliveries
+                .inner_join(webhooks::table)
+                .filter(dsl::status.eq(PENDING))
+                .filter(dsl::next_attempt_at.le(now))
+                .order(dsl::id.asc())
+                .limit(100)
+                .load::<(WebhookDelivery, Webhook)>(conn)
+        })
+        .await;
+
+    let due = match due {
+        Ok(due) => due,
+        Err(e) => {
+            println!("Error loading webhook deliveries: {:?}", e);
+            return 0;
+        }
+    };
+
+    let mut delivered = 0;
+
+    for (delivery, webhook) in due {
+        let res = send(client, &webhook, &delivery).await;
+        let attempts = delivery.attempts + 1;
+        let now = Utc::now().naive_utc();
+
+        let (status, next_attempt_at, response_status, last_error, delivered_at) = match res {
+            Ok(response_status) => {
+                delivered += 1;
+                (
+                    DELIVERED,
+                    None,
+                    Some(response_status as i32),
+                    None,
+                    Some(now),
+                )
+            }
+            Err(e) => {
+                println!("Webhook delivery {:?} failed: {}", delivery.id, e);
+
+                if attempts >= MAX_ATTEMPTS {
+                    (FAILED, None, None, Some(e), None)
+                } else {
+                    (PENDING, Some(now + backoff(attempts)), None, Some(e), None)
+                }
+            }
+        };
+
+        use crate::schema::webhook_deliveries::dsl;
+        if let Err(e) = diesel::update(dsl::webhook_deliveries.find(delivery.id))
+            .set((
+                dsl::status.eq(status),
+                dsl::attempts.eq(attempts),
+                dsl::next_attempt_at.eq(next_attempt_at),
+                dsl::response_status.eq(response_status),
+                dsl::last_error.eq(last_error),
+                dsl::delivered_at.eq(delivered_at),
+            ))
+            .execute_async(pool)
+            .await
+        {
+            println!("Error updating webhook delivery {:?}: {:?}", delivery.id, e);
+        }
+    }
+
+    delivered
+}
+
+// Queues renewals hourly and sends due deliveries every few seconds
+pub async fn webhook_db(pool: DbPool) {
+    // Redirects could lead to addresses check_url turned down
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_default();
+    let mut last_renewals: Option<NaiveDateTime> = None;
+
+    loop {
+        let now = Utc::now().naive_utc();
+
+        if last_renewals
+            .map(|last_renewals| now - last_renewals >= Duration::hours(1))
+            .unwrap_or(true)
+        {
+            match pool.run(queue_renewals).await {
+                Ok(queued) => println!("Renewal webhooks queued: {}", queued),
+                Err(e) => println!("Error queueing renewal webhooks: {:?}", e),
+            }
+            last_renewals = Some(now);
+        }
+
+        deliver_pending(&pool, &client).await;
+
+        tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn rejects_internal_urls() {
+        assert!(check_url("https://93.184.215.14/hook").await.is_ok());
+
+        for url in [
+            "ftp://93.184.215.14/hook",
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://172.16.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(check_url(url).await.is_err(), "{}", url);
+        }
+    }
+}